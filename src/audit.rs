@@ -0,0 +1,92 @@
+//! Admin page for browsing the audit log.
+
+use dioxus::prelude::*;
+
+use crate::error::ErrorView;
+use crate::history::HistoryFilter;
+use crate::server::{self, AuditAction, AuditFilter};
+use crate::Route;
+
+#[component]
+pub fn AuditLog() -> Element {
+    let mut filter = use_signal(AuditFilter::default);
+    let records = use_server_future(move || server::get_audit_log(filter()))?;
+    rsx! {
+        div {
+            class: "m-4 flex flex-wrap items-center gap-3",
+            h2 { class: "text-xl font-bold me-3", "Audit Log" }
+            input {
+                class: "border border-gray-300 rounded p-1",
+                placeholder: "User",
+                value: "{filter.read().user}",
+                onchange: move |event| filter.write().user = event.value(),
+            }
+            input {
+                class: "border border-gray-300 rounded p-1",
+                placeholder: "Patient ID",
+                value: "{filter.read().patient}",
+                onchange: move |event| filter.write().patient = event.value(),
+            }
+            label {
+                "From "
+                input {
+                    class: "border border-gray-300 rounded p-1",
+                    r#type: "date",
+                    value: filter.read().from.map(|date| date.to_string()).unwrap_or_default(),
+                    onchange: move |event| filter.write().from = event.value().parse().ok(),
+                }
+            }
+            label {
+                "To "
+                input {
+                    class: "border border-gray-300 rounded p-1",
+                    r#type: "date",
+                    value: filter.read().to.map(|date| date.to_string()).unwrap_or_default(),
+                    onchange: move |event| filter.write().to = event.value().parse().ok(),
+                }
+            }
+        }
+        match &*records.read_unchecked() {
+            Some(Ok(records)) => rsx! {
+                div {
+                    class: "grid gap-px p-px m-4",
+                    style: "grid-template-columns: repeat(4, auto)",
+                    div {
+                        class: "grid grid-cols-subgrid col-span-full",
+                        for header in ["Time", "User", "Action", "Patients"] {
+                            div { class: "outline outline-gray-300 p-2 bg-gray-100 font-bold", "{header}" }
+                        }
+                    }
+                    for record in records.iter() {
+                        div {
+                            class: "grid grid-cols-subgrid col-span-full",
+                            div { class: "outline outline-gray-300 p-2", "{crate::fhir::format_time(record.time)}" }
+                            div { class: "outline outline-gray-300 p-2", title: "{record.user}", "{record.user_name}" }
+                            div {
+                                class: "outline outline-gray-300 p-2",
+                                match record.action {
+                                    AuditAction::ListPatients => "Listed patients",
+                                    AuditAction::ViewPatient => "Viewed patient",
+                                    AuditAction::ViewResource => "Viewed resource",
+                                }
+                            }
+                            div {
+                                class: "outline outline-gray-300 p-2 flex flex-wrap gap-x-2",
+                                for id in record.patients.iter().cloned() {
+                                    Link { class: "underline", to: Route::PatientView { id: id.clone(), filter: HistoryFilter::default() }, "{id}" }
+                                }
+                            }
+                        }
+                    }
+                }
+                if records.is_empty() {
+                    p { class: "m-4", "No matching accesses" }
+                }
+            },
+            Some(Err(e)) => rsx! {
+                div { class: "m-4", ErrorView { title: "Error loading audit log", error: e.clone() } }
+            },
+            None => rsx! { p { class: "m-4", "Loading..." } },
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Display of the errors of the server functions that return patient data.
+
+use dioxus::prelude::*;
+
+use crate::server::ServerError;
+use crate::OptionalChip;
+
+/// A short description of an error for places where there is no room for
+/// [`ErrorView`].
+pub fn message(error: &ServerFnError<ServerError>) -> String {
+    match error {
+        ServerFnError::WrappedServerError(e) => e.message(),
+        e => e.to_string(),
+    }
+}
+
+/// Shows what went wrong, including the issues the FHIR server reported in
+/// its `OperationOutcome`.
+#[component]
+pub fn ErrorView(title: String, error: ServerFnError<ServerError>) -> Element {
+    let (heading, message, outcome) = match &error {
+        ServerFnError::WrappedServerError(
+            e @ ServerError::UpstreamInvalid {
+                status,
+                outcome: Some(outcome),
+            },
+        ) => (
+            e.title(),
+            format!("The FHIR server responded with {status}."),
+            Some(outcome.clone()),
+        ),
+        ServerFnError::WrappedServerError(e) => (e.title(), e.message(), None),
+        // The request did not reach Scout's server or its answer got lost
+        e => ("Connection error", e.to_string(), None),
+    };
+    rsx! {
+        div {
+            class: "my-3 p-2 border rounded bg-red-100 border-red-500",
+            p { class: "font-bold", "{title}: {heading}" }
+            p { class: "text-sm", "{message}" }
+            if let Some(outcome) = outcome {
+                ul {
+                    class: "mt-2 flex flex-col gap-1",
+                    for issue in outcome.issue {
+                        li {
+                            div {
+                                class: "inline-flex items-center gap-1.5",
+                                OptionalChip { chip: issue.severity_chip() }
+                                span { "{issue.message()}" }
+                            }
+                            if let Some(ref diagnostics) = issue.diagnostics {
+                                p { class: "text-sm text-gray-600", "{diagnostics}" }
+                            }
+                            if !issue.expression().is_empty() {
+                                p { class: "text-sm text-gray-600", "At: {issue.expression()}" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
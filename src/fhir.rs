@@ -1,5 +1,6 @@
 //! This module contains the data structures for the FHIR resources used in the application.
 
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -9,12 +10,207 @@ pub fn format_time(timestamp: jiff::Timestamp) -> String {
     zoned.strftime("%b %d, %Y, %H:%M %Z").to_string()
 }
 
+/// A FHIR `date`, which may be reduced to a month or a year.
+///
+/// http://hl7.org/fhir/R4/datatypes.html#date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Date {
+    Year(i16),
+    YearMonth(i16, i8),
+    Day(jiff::civil::Date),
+}
+
+impl Date {
+    /// The first day of the period the date covers.
+    pub fn first_day(&self) -> jiff::civil::Date {
+        match *self {
+            Date::Year(year) => jiff::civil::date(year, 1, 1),
+            Date::YearMonth(year, month) => jiff::civil::date(year, month, 1),
+            Date::Day(date) => date,
+        }
+    }
+
+    /// The last day of the period the date covers.
+    pub fn last_day(&self) -> jiff::civil::Date {
+        match *self {
+            Date::Year(year) => jiff::civil::date(year, 12, 31),
+            Date::YearMonth(year, month) => jiff::civil::date(year, month, 1).last_of_month(),
+            Date::Day(date) => date,
+        }
+    }
+}
+
+impl std::str::FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid FHIR date {s:?}");
+        let parts = s.split('-').collect::<Vec<_>>();
+        let number = |part: &str, len| {
+            (part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| part.parse::<i16>().ok())
+                .flatten()
+                .ok_or_else(invalid)
+        };
+        match parts[..] {
+            [year] => Ok(Date::Year(number(year, 4)?)),
+            [year, month] => {
+                let (year, month) = (number(year, 4)?, number(month, 2)?);
+                // Validates the month
+                jiff::civil::Date::new(year, month as i8, 1).map_err(|_| invalid())?;
+                Ok(Date::YearMonth(year, month as i8))
+            }
+            [_, _, _] => Ok(Date::Day(s.parse().map_err(|_| invalid())?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> Self {
+        match date {
+            Date::Year(year) => format!("{year:04}"),
+            Date::YearMonth(year, month) => format!("{year:04}-{month:02}"),
+            Date::Day(date) => date.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Date::Year(year) => write!(f, "{year}"),
+            // Mar 2020
+            Date::YearMonth(..) => write!(f, "{}", self.first_day().strftime("%b %Y")),
+            // Mar 15, 2020
+            Date::Day(date) => write!(f, "{}", date.strftime("%b %d, %Y")),
+        }
+    }
+}
+
+/// A FHIR `dateTime`, which is either a (partial) date or an instant with a
+/// time zone. FHIR `instant` values are plain [`jiff::Timestamp`]s.
+///
+/// Values are ordered by the start of the period they cover, so that `2020`
+/// comes before `2020-03-15`, which comes before `2020-03-15T10:00:00Z`.
+/// Dates without a time zone are placed in UTC.
+///
+/// http://hl7.org/fhir/R4/datatypes.html#dateTime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DateTime {
+    Date(Date),
+    Instant(jiff::Timestamp),
+}
+
+impl DateTime {
+    /// The earliest instant covered by the value.
+    pub fn start(&self) -> jiff::Timestamp {
+        match *self {
+            DateTime::Date(date) => date
+                .first_day()
+                .to_zoned(jiff::tz::TimeZone::UTC)
+                .map(|zoned| zoned.timestamp())
+                .unwrap_or(jiff::Timestamp::MIN),
+            DateTime::Instant(timestamp) => timestamp,
+        }
+    }
+
+    /// The value without its time, in the local time zone.
+    pub fn date(&self) -> Date {
+        match *self {
+            DateTime::Date(date) => date,
+            DateTime::Instant(timestamp) => {
+                Date::Day(timestamp.to_zoned(jiff::tz::TimeZone::system()).date())
+            }
+        }
+    }
+
+    /// 0 for a year up to 3 for an instant, used to order values with the
+    /// same start.
+    fn precision(&self) -> u8 {
+        match *self {
+            DateTime::Date(Date::Year(_)) => 0,
+            DateTime::Date(Date::YearMonth(..)) => 1,
+            DateTime::Date(Date::Day(_)) => 2,
+            DateTime::Instant(_) => 3,
+        }
+    }
+}
+
+impl Ord for DateTime {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.start(), self.precision()).cmp(&(other.start(), other.precision()))
+    }
+}
+
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for DateTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('T') {
+            // FHIR requires a time zone if a time is given
+            s.parse()
+                .map(DateTime::Instant)
+                .map_err(|_| format!("invalid FHIR dateTime {s:?}"))
+        } else {
+            s.parse().map(DateTime::Date)
+        }
+    }
+}
+
+impl TryFrom<String> for DateTime {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<DateTime> for String {
+    fn from(date_time: DateTime) -> Self {
+        match date_time {
+            DateTime::Date(date) => date.into(),
+            DateTime::Instant(timestamp) => timestamp.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DateTime::Date(date) => write!(f, "{date}"),
+            DateTime::Instant(timestamp) => write!(f, "{}", format_time(timestamp)),
+        }
+    }
+}
+
 /// http://hl7.org/fhir/StructureDefinition/HumanName
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct HumanName {
+    extension: Option<Vec<Extension>>,
     text: Option<String>,
     family: Option<String>,
-    given: Option<Vec<String>>,
+    #[serde(rename = "_family")]
+    family_element: Option<Element>,
+    given: Option<Vec<Option<String>>>,
+    #[serde(rename = "_given")]
+    given_element: Option<Vec<Option<Element>>>,
     prefix: Option<Vec<String>>,
     suffix: Option<Vec<String>>,
 }
@@ -24,16 +220,19 @@ impl fmt::Display for HumanName {
         if let Some(ref text) = self.text {
             write!(f, "{}", text)
         } else {
+            let family = value_or_absent(self.family.clone(), &self.family_element);
+            let given = values_or_absent(&self.given, &self.given_element);
             write!(
                 f,
                 "{}",
                 self.prefix
                     .iter()
                     .flatten()
-                    .chain(self.given.iter().flatten())
-                    .chain(self.family.iter())
+                    .chain(given.iter())
+                    .chain(Some(&family))
                     .chain(self.suffix.iter().flatten())
                     .map(|s| s.as_str())
+                    .filter(|s| !s.is_empty())
                     .collect::<Vec<_>>()
                     .join(" ")
             )
@@ -42,15 +241,29 @@ impl fmt::Display for HumanName {
 }
 
 /// http://hl7.org/fhir/StructureDefinition/Address
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct Address {
+    extension: Option<Vec<Extension>>,
     text: Option<String>,
-    line: Option<Vec<String>>,
+    line: Option<Vec<Option<String>>>,
+    #[serde(rename = "_line")]
+    line_element: Option<Vec<Option<Element>>>,
     city: Option<String>,
+    #[serde(rename = "_city")]
+    city_element: Option<Element>,
     district: Option<String>,
+    #[serde(rename = "_district")]
+    district_element: Option<Element>,
     state: Option<String>,
+    #[serde(rename = "_state")]
+    state_element: Option<Element>,
     postal_code: Option<String>,
+    #[serde(rename = "_postalCode")]
+    postal_code_element: Option<Element>,
     country: Option<String>,
+    #[serde(rename = "_country")]
+    country_element: Option<Element>,
 }
 
 impl fmt::Display for Address {
@@ -58,18 +271,19 @@ impl fmt::Display for Address {
         if let Some(ref text) = self.text {
             write!(f, "{}", text)
         } else {
+            let line = values_or_absent(&self.line, &self.line_element);
+            let city = value_or_absent(self.city.clone(), &self.city_element);
+            let district = value_or_absent(self.district.clone(), &self.district_element);
+            let state = value_or_absent(self.state.clone(), &self.state_element);
+            let postal_code = value_or_absent(self.postal_code.clone(), &self.postal_code_element);
+            let country = value_or_absent(self.country.clone(), &self.country_element);
             write!(
                 f,
                 "{}",
-                self.line
-                    .iter()
-                    .flatten()
-                    .chain(self.city.iter())
-                    .chain(self.district.iter())
-                    .chain(self.state.iter())
-                    .chain(self.postal_code.iter())
-                    .chain(self.country.iter())
+                line.iter()
+                    .chain([&city, &district, &state, &postal_code, &country])
                     .map(|s| s.as_str())
+                    .filter(|s| !s.is_empty())
                     .collect::<Vec<_>>()
                     .join(", ")
             )
@@ -83,10 +297,17 @@ impl fmt::Display for Address {
 #[serde(rename_all = "camelCase")]
 pub struct Patient {
     pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
     pub name: Option<Vec<HumanName>>,
     pub gender: Option<String>,
-    pub birth_date: Option<String>,
+    #[serde(rename = "_gender")]
+    pub gender_element: Option<Element>,
+    pub birth_date: Option<Date>,
+    #[serde(rename = "_birthDate")]
+    pub birth_date_element: Option<Element>,
     pub deceased_boolean: Option<bool>,
+    #[serde(rename = "_deceasedBoolean")]
+    pub deceased_boolean_element: Option<Element>,
     pub address: Option<Vec<Address>>,
 }
 
@@ -104,18 +325,35 @@ impl Patient {
             .join(", ")
     }
 
+    /// The administrative gender, qualified by the German `gender-amtlich-de`
+    /// extension if present, e.g. `other (divers)`.
     pub fn gender(&self) -> String {
-        self.gender.clone().unwrap_or_default()
+        let gender = value_or_absent(self.gender.clone(), &self.gender_element);
+        let amtlich = self
+            .gender_element
+            .as_ref()
+            .and_then(|element| {
+                element.extension("http://fhir.de/StructureDefinition/gender-amtlich-de")
+            })
+            .and_then(|extension| extension.value.as_ref());
+        match amtlich {
+            Some(amtlich) => format!("{gender} ({amtlich})"),
+            None => gender,
+        }
     }
 
     pub fn birth_date(&self) -> String {
-        self.birth_date.clone().unwrap_or_default()
+        value_or_absent(
+            self.birth_date.map(|date| date.to_string()),
+            &self.birth_date_element,
+        )
     }
 
     pub fn deceased(&self) -> String {
-        self.deceased_boolean
-            .map(|deceased| deceased.to_string())
-            .unwrap_or_default()
+        value_or_absent(
+            self.deceased_boolean.map(|deceased| deceased.to_string()),
+            &self.deceased_boolean_element,
+        )
     }
 
     pub fn address(&self) -> String {
@@ -128,12 +366,269 @@ impl Patient {
     }
 }
 
+/// The `value[x]` of an extension. Value types that are not listed here, e.g.
+/// `valueBase64Binary` or `valueSampledData`, are dropped.
+///
+/// http://hl7.org/fhir/R4/extensibility.html#Extension
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ExtensionValue {
+    #[serde(rename = "valueBoolean")]
+    Boolean(bool),
+    #[serde(rename = "valueInteger")]
+    Integer(i64),
+    #[serde(rename = "valuePositiveInt")]
+    PositiveInt(u32),
+    #[serde(rename = "valueUnsignedInt")]
+    UnsignedInt(u32),
+    #[serde(rename = "valueDecimal")]
+    Decimal(f64),
+    #[serde(rename = "valueString")]
+    String(String),
+    #[serde(rename = "valueCode")]
+    Code(String),
+    #[serde(rename = "valueId")]
+    Id(String),
+    #[serde(rename = "valueMarkdown")]
+    Markdown(String),
+    #[serde(rename = "valueUri")]
+    Uri(String),
+    #[serde(rename = "valueUrl")]
+    Url(String),
+    #[serde(rename = "valueCanonical")]
+    Canonical(String),
+    #[serde(rename = "valueDate")]
+    Date(Date),
+    #[serde(rename = "valueDateTime")]
+    DateTime(DateTime),
+    #[serde(rename = "valueInstant")]
+    Instant(jiff::Timestamp),
+    #[serde(rename = "valueTime")]
+    Time(jiff::civil::Time),
+    #[serde(rename = "valueCoding")]
+    Coding(Coding),
+    #[serde(rename = "valueCodeableConcept")]
+    CodeableConcept(CodeableConcept),
+    #[serde(rename = "valueQuantity")]
+    Quantity(Quantity),
+    #[serde(rename = "valuePeriod")]
+    Period(Period),
+    #[serde(rename = "valueRatio")]
+    Ratio(Ratio),
+    #[serde(rename = "valueReference")]
+    Reference(Reference),
+    #[serde(rename = "valueIdentifier")]
+    Identifier(Identifier),
+    #[serde(rename = "valueRange")]
+    Range(Range),
+    #[serde(rename = "valueAddress")]
+    Address(Address),
+    #[serde(rename = "valueHumanName")]
+    HumanName(HumanName),
+    #[serde(rename = "valueAttachment")]
+    Attachment(Attachment),
+    #[serde(rename = "valueAnnotation")]
+    Annotation(Annotation),
+}
+
+impl fmt::Display for ExtensionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtensionValue::Boolean(value) => write!(f, "{}", value),
+            ExtensionValue::Integer(value) => write!(f, "{}", value),
+            ExtensionValue::PositiveInt(value) | ExtensionValue::UnsignedInt(value) => {
+                write!(f, "{}", value)
+            }
+            ExtensionValue::Decimal(value) => write!(f, "{}", value),
+            ExtensionValue::String(value)
+            | ExtensionValue::Code(value)
+            | ExtensionValue::Id(value)
+            | ExtensionValue::Markdown(value)
+            | ExtensionValue::Uri(value)
+            | ExtensionValue::Url(value)
+            | ExtensionValue::Canonical(value) => write!(f, "{}", value),
+            ExtensionValue::Date(value) => write!(f, "{}", value),
+            ExtensionValue::DateTime(value) => write!(f, "{}", value),
+            ExtensionValue::Instant(value) => write!(f, "{}", format_time(*value)),
+            ExtensionValue::Time(value) => write!(f, "{}", value),
+            ExtensionValue::Coding(value) => write!(f, "{}", value),
+            ExtensionValue::CodeableConcept(value) => write!(f, "{}", value),
+            ExtensionValue::Quantity(value) => write!(f, "{}", value),
+            ExtensionValue::Period(value) => write!(
+                f,
+                "{} – {}",
+                value
+                    .start
+                    .map(|start| start.to_string())
+                    .unwrap_or_default(),
+                value.end.map(|end| end.to_string()).unwrap_or_default()
+            ),
+            ExtensionValue::Ratio(value) => write!(f, "{}", value),
+            ExtensionValue::Reference(value) => {
+                write!(f, "{}", value.reference.clone().unwrap_or_default())
+            }
+            ExtensionValue::Identifier(value) => write!(f, "{}", value),
+            ExtensionValue::Range(value) => write!(f, "{}", value),
+            ExtensionValue::Address(value) => write!(f, "{}", value),
+            ExtensionValue::HumanName(value) => write!(f, "{}", value),
+            ExtensionValue::Attachment(value) => write!(f, "{}", value),
+            ExtensionValue::Annotation(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Extension
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Extension {
+    pub url: String,
+    /// The parts of a complex extension
+    pub extension: Option<Vec<Extension>>,
+    #[serde(flatten)]
+    pub value: Option<ExtensionValue>,
+}
+
+impl Extension {
+    pub fn value_coding(&self) -> Option<&Coding> {
+        match self.value {
+            Some(ExtensionValue::Coding(ref coding)) => Some(coding),
+            _ => None,
+        }
+    }
+
+    pub fn value_reference(&self) -> Option<&Reference> {
+        match self.value {
+            Some(ExtensionValue::Reference(ref reference)) => Some(reference),
+            _ => None,
+        }
+    }
+
+    /// The value of a `valueCode` or `valueString`.
+    pub fn value_str(&self) -> Option<&str> {
+        match self.value {
+            Some(ExtensionValue::Code(ref value) | ExtensionValue::String(ref value)) => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+const DATA_ABSENT_REASON: &str = "http://hl7.org/fhir/StructureDefinition/data-absent-reason";
+
+/// Elements that can carry extensions.
+pub trait HasExtensions {
+    fn extensions(&self) -> &[Extension];
+
+    /// Returns the first extension with the given URL.
+    fn extension(&self, url: &str) -> Option<&Extension> {
+        self.extensions()
+            .iter()
+            .find(|extension| extension.url == url)
+    }
+
+    /// Why the value of the element is missing, e.g. `masked`.
+    ///
+    /// http://hl7.org/fhir/ValueSet/data-absent-reason
+    fn data_absent_reason(&self) -> Option<&str> {
+        self.extension(DATA_ABSENT_REASON)?.value_str()
+    }
+}
+
+/// Implements [`HasExtensions`] for types with an `extension` element.
+macro_rules! has_extensions {
+    ($($name:ty),* $(,)?) => {
+        $(
+            impl HasExtensions for $name {
+                fn extensions(&self) -> &[Extension] {
+                    self.extension.as_deref().unwrap_or_default()
+                }
+            }
+        )*
+    };
+}
+
+has_extensions!(
+    Extension,
+    Element,
+    HumanName,
+    Address,
+    Coding,
+    CodeableConcept,
+    Period,
+    Quantity,
+    Range,
+    Identifier,
+    Reference,
+    Attachment,
+    Annotation,
+    Patient,
+    Encounter,
+    Condition,
+    Procedure,
+    Observation,
+    Medication,
+    MedicationStatement,
+    MedicationAdministration,
+    MedicationRequest,
+    ReferenceRange,
+    Diagnosis,
+    Ratio,
+    TimingRepeat,
+    Timing,
+    DoseAndRate,
+    Dosage,
+    MedicationIngredient,
+    AdministrationDosage,
+);
+
+/// The `id` and extensions of a primitive value, which FHIR JSON puts into a
+/// separate `_name` property next to the value.
+///
+/// http://hl7.org/fhir/R4/json.html#primitive
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Element {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+}
+
+/// Shows a primitive value, or why it is missing, e.g. `unknown (masked)`.
+fn value_or_absent(value: Option<String>, element: &Option<Element>) -> String {
+    match (value, element) {
+        (Some(value), _) => value,
+        (None, Some(element)) => element
+            .data_absent_reason()
+            .map(|reason| format!("unknown ({reason})"))
+            .unwrap_or_default(),
+        (None, None) => String::new(),
+    }
+}
+
+/// Like [`value_or_absent`] for a repeating primitive, whose `_name` array
+/// lines up with the values.
+fn values_or_absent(
+    values: &Option<Vec<Option<String>>>,
+    elements: &Option<Vec<Option<Element>>>,
+) -> Vec<String> {
+    let values = values.as_deref().unwrap_or_default();
+    let elements = elements.as_deref().unwrap_or_default();
+    (0..values.len().max(elements.len()))
+        .map(|i| {
+            value_or_absent(
+                values.get(i).cloned().flatten(),
+                elements.get(i).unwrap_or(&None),
+            )
+        })
+        .collect()
+}
+
 /// http://hl7.org/fhir/StructureDefinition/Coding
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Coding {
     pub system: Option<String>,
     pub code: Option<String>,
+    #[serde(rename = "_code")]
+    pub code_element: Option<Element>,
     pub display: Option<String>,
+    pub extension: Option<Vec<Extension>>,
 }
 
 impl fmt::Display for Coding {
@@ -141,14 +636,19 @@ impl fmt::Display for Coding {
         if let Some(ref display) = self.display {
             write!(f, "{}", display)
         } else {
-            write!(f, "{}", self.code.clone().unwrap_or_default())
+            write!(
+                f,
+                "{}",
+                value_or_absent(self.code.clone(), &self.code_element)
+            )
         }
     }
 }
 
 /// http://hl7.org/fhir/StructureDefinition/CodeableConcept
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CodeableConcept {
+    pub extension: Option<Vec<Extension>>,
     pub coding: Option<Vec<Coding>>,
     pub text: Option<String>,
 }
@@ -183,37 +683,213 @@ impl CodeableConcept {
 }
 
 /// http://hl7.org/fhir/StructureDefinition/Period
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Period {
-    pub start: Option<jiff::Timestamp>,
-    pub end: Option<jiff::Timestamp>,
+    pub extension: Option<Vec<Extension>>,
+    pub start: Option<DateTime>,
+    pub end: Option<DateTime>,
 }
 
-/// http://hl7.org/fhir/StructureDefinition/Identifier
+/// http://hl7.org/fhir/StructureDefinition/Quantity
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Quantity {
+    pub extension: Option<Vec<Extension>>,
+    pub value: Option<f64>,
+    pub comparator: Option<String>,
+    pub unit: Option<String>,
+    pub system: Option<String>,
+    pub code: Option<String>,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = self.unit.as_ref().or(self.code.as_ref());
+        write!(
+            f,
+            "{}{}",
+            self.comparator.clone().unwrap_or_default(),
+            self.value
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        )?;
+        if let Some(unit) = unit {
+            write!(f, " {}", unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Observation#Observation.referenceRange
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceRange {
+    pub extension: Option<Vec<Extension>>,
+    pub low: Option<Quantity>,
+    pub high: Option<Quantity>,
+    pub text: Option<String>,
+}
+
+impl fmt::Display for ReferenceRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref text) = self.text {
+            return write!(f, "{}", text);
+        }
+        match (&self.low, &self.high) {
+            (Some(low), Some(high)) => write!(
+                f,
+                "{} – {}",
+                low.value.map(|value| value.to_string()).unwrap_or_default(),
+                high
+            ),
+            (Some(low), None) => write!(f, "≥ {}", low),
+            (None, Some(high)) => write!(f, "≤ {}", high),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Identifier
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Identifier {
+    pub extension: Option<Vec<Extension>>,
     pub r#type: Option<CodeableConcept>,
     pub value: Option<String>,
+    #[serde(rename = "_value")]
+    pub value_element: Option<Element>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            value_or_absent(self.value.clone(), &self.value_element)
+        )
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Range
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Range {
+    pub extension: Option<Vec<Extension>>,
+    pub low: Option<Quantity>,
+    pub high: Option<Quantity>,
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.low, &self.high) {
+            (Some(low), Some(high)) => write!(f, "{} – {}", low, high),
+            (Some(low), None) => write!(f, "≥ {}", low),
+            (None, Some(high)) => write!(f, "≤ {}", high),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Attachment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub extension: Option<Vec<Extension>>,
+    pub content_type: Option<String>,
+    pub url: Option<String>,
+    pub title: Option<String>,
+}
+
+impl fmt::Display for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.title.as_ref().or(self.url.as_ref());
+        write!(
+            f,
+            "{}",
+            name.or(self.content_type.as_ref())
+                .cloned()
+                .unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Reference {
+    pub extension: Option<Vec<Extension>>,
     pub reference: Option<String>,
     pub identifier: Option<Identifier>,
 }
 
+impl Reference {
+    /// The ID of the referenced resource if it is of the given type, e.g. `123`
+    /// for `Encounter/123`. Absolute references are matched by their last two
+    /// segments.
+    pub fn id_of(&self, resource_type: &str) -> Option<&str> {
+        let (path, id) = self.reference.as_deref()?.rsplit_once('/')?;
+        let matches = path == resource_type || path.ends_with(&format!("/{resource_type}"));
+        (matches && !id.is_empty()).then_some(id)
+    }
+}
+
 /// http://hl7.org/fhir/StructureDefinition/Annotation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Annotation {
-    pub time: Option<jiff::Timestamp>,
+    pub extension: Option<Vec<Extension>>,
+    pub time: Option<DateTime>,
     pub text: String,
 }
 
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.time {
+            Some(time) => write!(f, "{}: {}", time, self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Encounter#Encounter.diagnosis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnosis {
+    pub extension: Option<Vec<Extension>>,
+    pub condition: Reference,
+    pub r#use: Option<CodeableConcept>,
+    pub rank: Option<u32>,
+}
+
+impl Diagnosis {
+    /// The roles of the diagnosis in the encounter. Codes of
+    /// http://terminology.hl7.org/CodeSystem/diagnosis-role are shown with the
+    /// German names used in the MII, other codes with their display.
+    pub fn role(&self) -> String {
+        self.r#use
+            .iter()
+            .flat_map(|r#use| r#use.coding.iter().flatten())
+            .map(|coding| {
+                let role = match coding.code.as_deref() {
+                    _ if coding.system.as_deref()
+                        != Some("http://terminology.hl7.org/CodeSystem/diagnosis-role") =>
+                    {
+                        None
+                    }
+                    Some("AD") => Some("Aufnahmediagnose"),
+                    Some("DD") => Some("Entlassdiagnose"),
+                    Some("CC") => Some("Hauptdiagnose"),
+                    Some("CM") => Some("Komorbidität"),
+                    Some("pre-op") => Some("Präoperative Diagnose"),
+                    Some("post-op") => Some("Postoperative Diagnose"),
+                    Some("billing") => Some("Abrechnungsdiagnose"),
+                    _ => None,
+                };
+                role.map(String::from).unwrap_or_else(|| coding.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
 /// https://www.medizininformatik-initiative.de/fhir/core/modul-fall/StructureDefinition/KontaktGesundheitseinrichtung
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Encounter {
     pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
     pub identifier: Option<Vec<Identifier>>,
     pub status: String,
     pub class: Coding,
@@ -221,6 +897,8 @@ pub struct Encounter {
     pub service_type: Option<CodeableConcept>,
     pub period: Option<Period>,
     pub service_provider: Option<Reference>,
+    pub part_of: Option<Reference>,
+    pub diagnosis: Option<Vec<Diagnosis>>,
 }
 
 impl Encounter {
@@ -277,20 +955,6 @@ impl Encounter {
             .unwrap_or_default()
     }
 
-    /// http://fhir.de/CodeSystem/kontaktart-de
-    pub fn encounter_type(&self) -> String {
-        self.r#type
-            .iter()
-            .flatten()
-            .find(|r#type| {
-                r#type.coding.iter().flatten().any(|coding| {
-                    coding.system == Some("http://fhir.de/CodeSystem/kontaktart-de".into())
-                })
-            })
-            .map(|r#type| r#type.to_string())
-            .unwrap_or_default()
-    }
-
     pub fn service_type(&self) -> String {
         self.service_type
             .as_ref()
@@ -298,6 +962,15 @@ impl Encounter {
             .unwrap_or_default()
     }
 
+    /// The diagnoses in rank order, diagnoses without a rank last.
+    pub fn diagnoses(&self) -> Vec<&Diagnosis> {
+        self.diagnosis
+            .iter()
+            .flatten()
+            .sorted_by_key(|diagnosis| diagnosis.rank.unwrap_or(u32::MAX))
+            .collect()
+    }
+
     pub fn service_provider(&self) -> String {
         self.service_provider
             .as_ref()
@@ -307,23 +980,27 @@ impl Encounter {
 }
 
 impl TimelineEvent for Encounter {
-    fn timestamp(&self) -> Option<jiff::Timestamp> {
+    fn timestamp(&self) -> Option<DateTime> {
         self.period.as_ref().and_then(|period| period.start)
     }
 }
 
+const ICD_10_GM: &str = "http://fhir.de/CodeSystem/bfarm/icd-10-gm";
+
 /// https://www.medizininformatik-initiative.de/fhir/core/modul-diagnose/StructureDefinition/Diagnose
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Condition {
     pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
     pub clinical_status: Option<CodeableConcept>,
     pub verification_status: Option<CodeableConcept>,
     pub code: CodeableConcept,
     pub body_site: Option<Vec<CodeableConcept>>,
     pub onset_period: Option<Period>,
-    pub onset_date_time: Option<jiff::Timestamp>,
-    pub recorded_date: jiff::Timestamp,
+    pub onset_date_time: Option<DateTime>,
+    pub recorded_date: DateTime,
+    pub encounter: Option<Reference>,
     pub note: Option<Vec<Annotation>>,
 }
 
@@ -345,13 +1022,6 @@ impl Condition {
         }
     }
 
-    pub fn clinical_status(&self) -> String {
-        self.clinical_status
-            .as_ref()
-            .map(|status| status.to_string())
-            .unwrap_or_default()
-    }
-
     /// http://hl7.org/fhir/ValueSet/condition-ver-status
     pub fn verification_status_chip(&self) -> Option<Chip> {
         match self.verification_status.as_ref()?.code_in_system("http://terminology.hl7.org/CodeSystem/condition-ver-status")?.as_str() {
@@ -365,15 +1035,108 @@ impl Condition {
         }
     }
 
-    pub fn verification_status(&self) -> String {
-        self.verification_status
-            .as_ref()
-            .map(|status| status.to_string())
-            .unwrap_or_default()
+    /// The codings with the ICD-10-GM code shown with its Mehrfachkodierung
+    /// marker, e.g. `A54.4† Gonokokkeninfektion des Muskel-Skelett-Systems`.
+    pub fn code(&self) -> String {
+        if let Some(ref text) = self.code.text {
+            return text.clone();
+        }
+        self.code
+            .coding
+            .iter()
+            .flatten()
+            .map(|coding| match coding.system.as_deref() {
+                Some(ICD_10_GM) => format!(
+                    "{}{} {}",
+                    coding.code.clone().unwrap_or_default(),
+                    Condition::multi_coding_marker(coding).unwrap_or_default(),
+                    coding.display.clone().unwrap_or_default()
+                )
+                .trim_end()
+                .to_string(),
+                _ => coding.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 
-    pub fn code(&self) -> String {
-        self.code.to_string()
+    fn icd_10_gm(&self) -> Option<&Coding> {
+        self.code
+            .coding
+            .iter()
+            .flatten()
+            .find(|coding| coding.system.as_deref() == Some(ICD_10_GM))
+    }
+
+    /// The value of an extension of the ICD-10-GM coding.
+    fn icd_10_gm_extension(&self, url: &str) -> Option<&Coding> {
+        self.icd_10_gm()?.extension(url)?.value_coding()
+    }
+
+    /// `†` for a primary code, `*` for a secondary code and `!` for an
+    /// additional code of an ICD-10-GM coding.
+    ///
+    /// http://fhir.de/ValueSet/icd-10-gm-mehrfachcodierungs-kennzeichen
+    fn multi_coding_marker(coding: &Coding) -> Option<String> {
+        coding
+            .extension(
+                "http://fhir.de/StructureDefinition/icd-10-gm-mehrfachcodierungs-kennzeichen",
+            )?
+            .value_coding()?
+            .code
+            .clone()
+    }
+
+    /// https://fhir.kbv.de/ValueSet/KBV_VS_SFHIR_ICD_DIAGNOSESICHERHEIT
+    pub fn diagnosis_certainty_chip(&self) -> Option<Chip> {
+        let certainty = self.icd_10_gm_extension(
+            "http://fhir.de/StructureDefinition/icd-10-gm-diagnosesicherheit",
+        )?;
+        match certainty.code.as_deref()? {
+            "A" => Some(Chip::new(
+                "bg-red-100 border-red-500",
+                "Ausgeschlossen",
+                "Ausgeschlossene Diagnose",
+            )),
+            "G" => Some(Chip::new(
+                "bg-green-100 border-green-500",
+                "Gesichert",
+                "Gesicherte Diagnose",
+            )),
+            "V" => Some(Chip::new(
+                "bg-yellow-100 border-yellow-500",
+                "Verdacht",
+                "Verdachtsdiagnose",
+            )),
+            "Z" => Some(Chip::new(
+                "bg-gray-100 border-gray-500",
+                "Zustand nach",
+                "Zustand nach der betreffenden Diagnose",
+            )),
+            _ => None,
+        }
+    }
+
+    /// https://fhir.kbv.de/ValueSet/KBV_VS_SFHIR_ICD_SEITENLOKALISATION
+    pub fn laterality(&self) -> String {
+        let Some(laterality) =
+            self.icd_10_gm_extension("http://fhir.de/StructureDefinition/seitenlokalisation")
+        else {
+            return String::new();
+        };
+        match (laterality.display.as_deref(), laterality.code.as_deref()) {
+            (Some(display), _) => display.to_string(),
+            (None, Some("L")) => "links".to_string(),
+            (None, Some("R")) => "rechts".to_string(),
+            (None, Some("B")) => "beidseitig".to_string(),
+            (None, code) => code.unwrap_or_default().to_string(),
+        }
+    }
+
+    /// The primary code a secondary code (`*` or `!`) belongs to.
+    pub fn related_condition(&self) -> Option<&Reference> {
+        self.extension("http://hl7.org/fhir/StructureDefinition/condition-related")?
+            .value_reference()
     }
 
     pub fn body_site(&self) -> String {
@@ -390,22 +1153,13 @@ impl Condition {
             .as_ref()
             .and_then(|period| period.start)
             .or(self.onset_date_time)
-            .map(|timestamp| format_time(timestamp))
+            .map(|onset| onset.to_string())
             .unwrap_or_default()
     }
-
-    pub fn note(&self) -> String {
-        self.note
-            .iter()
-            .flatten()
-            .map(|note| note.text.clone())
-            .collect::<Vec<_>>()
-            .join(", ")
-    }
 }
 
 impl TimelineEvent for Condition {
-    fn timestamp(&self) -> Option<jiff::Timestamp> {
+    fn timestamp(&self) -> Option<DateTime> {
         Some(self.recorded_date)
     }
 }
@@ -415,12 +1169,14 @@ impl TimelineEvent for Condition {
 #[serde(rename_all = "camelCase")]
 pub struct Procedure {
     pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
     pub status: String,
     pub category: Option<CodeableConcept>,
     pub code: CodeableConcept,
-    pub performed_date_time: Option<jiff::Timestamp>,
+    pub performed_date_time: Option<DateTime>,
     pub performed_period: Option<Period>,
     pub body_site: Option<Vec<CodeableConcept>>,
+    pub encounter: Option<Reference>,
     pub note: Option<Vec<Annotation>>,
 }
 
@@ -463,19 +1219,10 @@ impl Procedure {
             .collect::<Vec<_>>()
             .join(", ")
     }
-
-    pub fn note(&self) -> String {
-        self.note
-            .iter()
-            .flatten()
-            .map(|note| note.text.clone())
-            .collect::<Vec<_>>()
-            .join(", ")
-    }
 }
 
 impl TimelineEvent for Procedure {
-    fn timestamp(&self) -> Option<jiff::Timestamp> {
+    fn timestamp(&self) -> Option<DateTime> {
         self.performed_period
             .as_ref()
             .and_then(|period| period.start)
@@ -483,28 +1230,698 @@ impl TimelineEvent for Procedure {
     }
 }
 
+/// https://www.medizininformatik-initiative.de/fhir/core/modul-labor/StructureDefinition/ObservationLab
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Observation {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+    pub status: String,
+    pub category: Option<Vec<CodeableConcept>>,
+    pub code: CodeableConcept,
+    pub effective_date_time: Option<DateTime>,
+    pub effective_period: Option<Period>,
+    pub value_quantity: Option<Quantity>,
+    pub value_codeable_concept: Option<CodeableConcept>,
+    pub value_string: Option<String>,
+    pub interpretation: Option<Vec<CodeableConcept>>,
+    pub reference_range: Option<Vec<ReferenceRange>>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+impl Observation {
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_default()
+    }
+
+    /// http://hl7.org/fhir/ValueSet/observation-status
+    pub fn status_chip(&self) -> Option<Chip> {
+        match self.status.as_str() {
+            "registered" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Registered", "The existence of the observation is registered, but there is no result yet available.")),
+            "preliminary" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Preliminary", "This is an initial or interim observation: data may be incomplete or unverified.")),
+            "final" => Some(Chip::new("bg-green-100 border-green-500", "Final", "The observation is complete and there are no further actions needed.")),
+            "amended" => Some(Chip::new("bg-green-100 border-green-500", "Amended", "Subsequent to being Final, the observation has been modified subsequent. This includes updates/new information and corrections.")),
+            "corrected" => Some(Chip::new("bg-green-100 border-green-500", "Corrected", "Subsequent to being Final, the observation has been modified to correct an error in the test result.")),
+            "cancelled" => Some(Chip::new("bg-red-100 border-red-500", "Cancelled", "The observation is unavailable because the measurement was not started or not completed.")),
+            "entered-in-error" => Some(Chip::new("bg-purple-100 border-purple-500", "Entered in Error", "The observation has been withdrawn following previous final release. This electronic record should never have existed, though it is possible that real-world decisions were based on it.")),
+            "unknown" => Some(Chip::new("bg-gray-100 border-gray-500", "Unknown", "The authoring/source system does not know which of the status values currently applies for this observation.")),
+            _ => None,
+        }
+    }
+
+    /// http://hl7.org/fhir/ValueSet/observation-interpretation
+    pub fn interpretation_code(&self) -> Option<String> {
+        self.interpretation
+            .iter()
+            .flatten()
+            .find_map(|interpretation| {
+                interpretation.code_in_system(
+                    "http://terminology.hl7.org/CodeSystem/v3-ObservationInterpretation",
+                )
+            })
+    }
+
+    /// http://hl7.org/fhir/ValueSet/observation-interpretation
+    pub fn interpretation_chip(&self) -> Option<Chip> {
+        match self.interpretation_code()?.as_str() {
+            "HH" | "HU" => Some(Chip::new(
+                "bg-red-100 border-red-500",
+                "Critical High",
+                "The result is above the upper panic limits.",
+            )),
+            "LL" | "LU" => Some(Chip::new(
+                "bg-red-100 border-red-500",
+                "Critical Low",
+                "The result is below the lower panic limits.",
+            )),
+            "AA" => Some(Chip::new(
+                "bg-red-100 border-red-500",
+                "Critical",
+                "The result is outside a reference range at a level that is critical.",
+            )),
+            "H" => Some(Chip::new(
+                "bg-orange-100 border-orange-500",
+                "High",
+                "The result is above the upper limit of the reference range.",
+            )),
+            "L" => Some(Chip::new(
+                "bg-blue-100 border-blue-500",
+                "Low",
+                "The result is below the lower limit of the reference range.",
+            )),
+            "A" => Some(Chip::new(
+                "bg-orange-100 border-orange-500",
+                "Abnormal",
+                "The result is outside a reference range.",
+            )),
+            ">" => Some(Chip::new(
+                "bg-orange-100 border-orange-500",
+                "Off Scale High",
+                "The result is above the upper limit of the measurement.",
+            )),
+            "<" => Some(Chip::new(
+                "bg-blue-100 border-blue-500",
+                "Off Scale Low",
+                "The result is below the lower limit of the measurement.",
+            )),
+            "N" => Some(Chip::new(
+                "bg-green-100 border-green-500",
+                "Normal",
+                "The result is within the reference range.",
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn category(&self) -> String {
+        self.category
+            .iter()
+            .flatten()
+            .map(|category| category.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn code(&self) -> String {
+        self.code.to_string()
+    }
+
+    pub fn value(&self) -> String {
+        if let Some(ref quantity) = self.value_quantity {
+            quantity.to_string()
+        } else if let Some(ref concept) = self.value_codeable_concept {
+            concept.to_string()
+        } else {
+            self.value_string.clone().unwrap_or_default()
+        }
+    }
+
+    pub fn reference_range(&self) -> String {
+        self.reference_range
+            .iter()
+            .flatten()
+            .map(|range| range.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl TimelineEvent for Observation {
+    fn timestamp(&self) -> Option<DateTime> {
+        self.effective_date_time.or_else(|| {
+            self.effective_period
+                .as_ref()
+                .and_then(|period| period.start)
+        })
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Ratio
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Ratio {
+    pub extension: Option<Vec<Extension>>,
+    pub numerator: Option<Quantity>,
+    pub denominator: Option<Quantity>,
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.numerator, &self.denominator) {
+            (Some(numerator), Some(denominator)) => write!(f, "{} / {}", numerator, denominator),
+            (Some(numerator), None) => write!(f, "{}", numerator),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Timing#Timing.repeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimingRepeat {
+    pub extension: Option<Vec<Extension>>,
+    pub frequency: Option<u32>,
+    pub period: Option<f64>,
+    pub period_unit: Option<String>,
+    pub when: Option<Vec<String>>,
+    pub time_of_day: Option<Vec<String>>,
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Timing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timing {
+    pub extension: Option<Vec<Extension>>,
+    pub event: Option<Vec<DateTime>>,
+    pub repeat: Option<TimingRepeat>,
+    pub code: Option<CodeableConcept>,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref code) = self.code {
+            return write!(f, "{}", code);
+        }
+        let mut parts = Vec::new();
+        if let Some(ref repeat) = self.repeat {
+            if let Some(frequency) = repeat.frequency {
+                // 3× per 1 d
+                let mut part = format!("{}×", frequency);
+                if let Some(period) = repeat.period {
+                    part.push_str(&format!(" per {}", period));
+                }
+                if let Some(ref unit) = repeat.period_unit {
+                    part.push_str(&format!(" {}", unit));
+                }
+                parts.push(part);
+            }
+            parts.extend(repeat.when.iter().flatten().cloned());
+            parts.extend(repeat.time_of_day.iter().flatten().cloned());
+        }
+        parts.extend(self.event.iter().flatten().map(|event| event.to_string()));
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Dosage#Dosage.doseAndRate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoseAndRate {
+    pub extension: Option<Vec<Extension>>,
+    pub dose_quantity: Option<Quantity>,
+    pub rate_ratio: Option<Ratio>,
+    pub rate_quantity: Option<Quantity>,
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Dosage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dosage {
+    pub extension: Option<Vec<Extension>>,
+    pub text: Option<String>,
+    pub timing: Option<Timing>,
+    pub as_needed_boolean: Option<bool>,
+    pub route: Option<CodeableConcept>,
+    pub dose_and_rate: Option<Vec<DoseAndRate>>,
+}
+
+impl fmt::Display for Dosage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref text) = self.text {
+            return write!(f, "{}", text);
+        }
+        let mut parts = Vec::new();
+        for dose_and_rate in self.dose_and_rate.iter().flatten() {
+            parts.extend(
+                dose_and_rate
+                    .dose_quantity
+                    .iter()
+                    .map(|dose| dose.to_string()),
+            );
+            parts.extend(dose_and_rate.rate_ratio.iter().map(|rate| rate.to_string()));
+            parts.extend(
+                dose_and_rate
+                    .rate_quantity
+                    .iter()
+                    .map(|rate| rate.to_string()),
+            );
+        }
+        parts.extend(self.timing.iter().map(|timing| timing.to_string()));
+        parts.extend(self.route.iter().map(|route| route.to_string()));
+        if self.as_needed_boolean == Some(true) {
+            parts.push("as needed".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/Medication#Medication.ingredient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationIngredient {
+    pub extension: Option<Vec<Extension>>,
+    pub item_codeable_concept: Option<CodeableConcept>,
+    pub is_active: Option<bool>,
+    pub strength: Option<Ratio>,
+}
+
+impl fmt::Display for MedicationIngredient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.item_codeable_concept
+                .as_ref()
+                .map(|item| item.to_string())
+                .unwrap_or_default()
+        )?;
+        if let Some(ref strength) = self.strength {
+            write!(f, " {}", strength)?;
+        }
+        Ok(())
+    }
+}
+
+/// https://www.medizininformatik-initiative.de/fhir/core/modul-medikation/StructureDefinition/Medication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Medication {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+    pub code: Option<CodeableConcept>,
+    pub form: Option<CodeableConcept>,
+    pub amount: Option<Ratio>,
+    pub ingredient: Option<Vec<MedicationIngredient>>,
+}
+
+impl Medication {
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_default()
+    }
+
+    /// The code of the medication, or its ingredients if it has none, e.g. for
+    /// compounded medications.
+    pub fn code(&self) -> String {
+        match self.code {
+            Some(ref code) => code.to_string(),
+            None => self
+                .ingredient
+                .iter()
+                .flatten()
+                .filter_map(|ingredient| ingredient.item_codeable_concept.as_ref())
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    pub fn form(&self) -> String {
+        self.form
+            .as_ref()
+            .map(|form| form.to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn ingredients(&self) -> String {
+        self.ingredient
+            .iter()
+            .flatten()
+            .map(|ingredient| ingredient.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The `medication[x]` element shared by the medication resources. A
+/// `medicationReference` is resolved against the bundle the resource came in.
+fn medication<'a>(
+    concept: &'a Option<CodeableConcept>,
+    reference: &Option<Reference>,
+    bundle: &'a MixedBundle,
+) -> (String, Option<&'a Medication>) {
+    match (concept, reference) {
+        (Some(concept), _) => (concept.to_string(), None),
+        (None, Some(reference)) => match bundle.medication(reference) {
+            Some(medication) => (medication.code(), Some(medication)),
+            None => (reference.reference.clone().unwrap_or_default(), None),
+        },
+        (None, None) => (String::new(), None),
+    }
+}
+
+/// https://www.medizininformatik-initiative.de/fhir/core/modul-medikation/StructureDefinition/MedicationStatement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationStatement {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+    pub status: String,
+    pub medication_codeable_concept: Option<CodeableConcept>,
+    pub medication_reference: Option<Reference>,
+    pub effective_date_time: Option<DateTime>,
+    pub effective_period: Option<Period>,
+    pub date_asserted: Option<DateTime>,
+    pub reason_code: Option<Vec<CodeableConcept>>,
+    pub dosage: Option<Vec<Dosage>>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+impl MedicationStatement {
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_default()
+    }
+
+    /// http://hl7.org/fhir/ValueSet/medication-statement-status
+    pub fn status_chip(&self) -> Option<Chip> {
+        match self.status.as_str() {
+            "active" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Active", "The medication is still being taken.")),
+            "completed" => Some(Chip::new("bg-green-100 border-green-500", "Completed", "The medication is no longer being taken.")),
+            "entered-in-error" => Some(Chip::new("bg-purple-100 border-purple-500", "Entered in Error", "Some of the actions that are implied by the medication statement may have occurred. For example, the patient may have taken some of the medication. Clinical decision support systems should take this status into account.")),
+            "intended" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Intended", "The medication may be taken at some time in the future.")),
+            "stopped" => Some(Chip::new("bg-purple-100 border-purple-500", "Stopped", "Actions implied by the statement have been permanently halted, before all of them occurred. This should not be used if the statement was entered in error.")),
+            "on-hold" => Some(Chip::new("bg-yellow-100 border-yellow-500", "On Hold", "Actions implied by the statement have been temporarily halted, but are expected to continue later. May also be called 'suspended'.")),
+            "unknown" => Some(Chip::new("bg-gray-100 border-gray-500", "Unknown", "The state of the medication use is not currently known.")),
+            "not-taken" => Some(Chip::new("bg-red-100 border-red-500", "Not Taken", "The medication was not consumed by the patient.")),
+            _ => None,
+        }
+    }
+
+    pub fn medication<'a>(&'a self, bundle: &'a MixedBundle) -> (String, Option<&'a Medication>) {
+        medication(
+            &self.medication_codeable_concept,
+            &self.medication_reference,
+            bundle,
+        )
+    }
+
+    pub fn reason(&self) -> String {
+        self.reason_code
+            .iter()
+            .flatten()
+            .map(|reason| reason.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn dosage(&self) -> String {
+        self.dosage
+            .iter()
+            .flatten()
+            .map(|dosage| dosage.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl TimelineEvent for MedicationStatement {
+    fn timestamp(&self) -> Option<DateTime> {
+        self.effective_date_time
+            .or_else(|| {
+                self.effective_period
+                    .as_ref()
+                    .and_then(|period| period.start)
+            })
+            .or(self.date_asserted)
+    }
+}
+
+/// http://hl7.org/fhir/StructureDefinition/MedicationAdministration#MedicationAdministration.dosage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdministrationDosage {
+    pub extension: Option<Vec<Extension>>,
+    pub text: Option<String>,
+    pub route: Option<CodeableConcept>,
+    pub dose: Option<Quantity>,
+    pub rate_ratio: Option<Ratio>,
+    pub rate_quantity: Option<Quantity>,
+}
+
+impl fmt::Display for AdministrationDosage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref text) = self.text {
+            return write!(f, "{}", text);
+        }
+        let parts = self
+            .dose
+            .iter()
+            .map(|dose| dose.to_string())
+            .chain(self.rate_ratio.iter().map(|rate| rate.to_string()))
+            .chain(self.rate_quantity.iter().map(|rate| rate.to_string()))
+            .chain(self.route.iter().map(|route| route.to_string()))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// https://www.medizininformatik-initiative.de/fhir/core/modul-medikation/StructureDefinition/MedicationAdministration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationAdministration {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+    pub status: String,
+    pub medication_codeable_concept: Option<CodeableConcept>,
+    pub medication_reference: Option<Reference>,
+    pub effective_date_time: Option<DateTime>,
+    pub effective_period: Option<Period>,
+    pub dosage: Option<AdministrationDosage>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+impl MedicationAdministration {
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_default()
+    }
+
+    /// http://hl7.org/fhir/ValueSet/medication-admin-status
+    pub fn status_chip(&self) -> Option<Chip> {
+        match self.status.as_str() {
+            "in-progress" => Some(Chip::new("bg-yellow-100 border-yellow-500", "In Progress", "The administration has started but has not yet completed.")),
+            "not-done" => Some(Chip::new("bg-purple-100 border-purple-500", "Not Done", "The administration was terminated prior to any impact on the subject (though preparatory actions may have been taken).")),
+            "on-hold" => Some(Chip::new("bg-yellow-100 border-yellow-500", "On Hold", "Actions implied by the administration have been temporarily halted, but are expected to continue later. May also be called 'suspended'.")),
+            "completed" => Some(Chip::new("bg-green-100 border-green-500", "Completed", "All actions that are implied by the administration have occurred.")),
+            "entered-in-error" => Some(Chip::new("bg-purple-100 border-purple-500", "Entered in Error", "The administration was entered in error and therefore nullified.")),
+            "stopped" => Some(Chip::new("bg-purple-100 border-purple-500", "Stopped", "Actions implied by the administration have been permanently halted, before all of them occurred.")),
+            "unknown" => Some(Chip::new("bg-gray-100 border-gray-500", "Unknown", "The authoring system does not know which of the status values currently applies for this request. Note: This concept is not to be used for 'other' - one of the listed statuses is presumed to apply, it's just not known which one.")),
+            _ => None,
+        }
+    }
+
+    pub fn medication<'a>(&'a self, bundle: &'a MixedBundle) -> (String, Option<&'a Medication>) {
+        medication(
+            &self.medication_codeable_concept,
+            &self.medication_reference,
+            bundle,
+        )
+    }
+
+    pub fn dosage(&self) -> String {
+        self.dosage
+            .as_ref()
+            .map(|dosage| dosage.to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl TimelineEvent for MedicationAdministration {
+    fn timestamp(&self) -> Option<DateTime> {
+        self.effective_date_time.or_else(|| {
+            self.effective_period
+                .as_ref()
+                .and_then(|period| period.start)
+        })
+    }
+}
+
+/// https://www.medizininformatik-initiative.de/fhir/core/modul-medikation/StructureDefinition/MedicationRequest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationRequest {
+    pub id: Option<String>,
+    pub extension: Option<Vec<Extension>>,
+    pub status: String,
+    pub intent: String,
+    pub medication_codeable_concept: Option<CodeableConcept>,
+    pub medication_reference: Option<Reference>,
+    pub authored_on: Option<DateTime>,
+    pub dosage_instruction: Option<Vec<Dosage>>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+impl MedicationRequest {
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_default()
+    }
+
+    /// http://hl7.org/fhir/ValueSet/medicationrequest-status
+    pub fn status_chip(&self) -> Option<Chip> {
+        match self.status.as_str() {
+            "active" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Active", "The prescription is 'actionable', but not all actions that are implied by it have occurred yet.")),
+            "on-hold" => Some(Chip::new("bg-yellow-100 border-yellow-500", "On Hold", "Actions implied by the prescription are to be temporarily halted, but are expected to continue later. May also be called 'suspended'.")),
+            "cancelled" => Some(Chip::new("bg-red-100 border-red-500", "Cancelled", "The prescription has been withdrawn before any administrations have occurred.")),
+            "completed" => Some(Chip::new("bg-green-100 border-green-500", "Completed", "All actions that are implied by the prescription have occurred.")),
+            "entered-in-error" => Some(Chip::new("bg-purple-100 border-purple-500", "Entered in Error", "Some of the actions that are implied by the medication request may have occurred. For example, the medication may have been dispensed and the patient may have taken some of the medication. Clinical decision support systems should take this status into account.")),
+            "stopped" => Some(Chip::new("bg-purple-100 border-purple-500", "Stopped", "Actions implied by the prescription are to be permanently halted, before all of them occurred. This should not be used if the original order was entered in error.")),
+            "draft" => Some(Chip::new("bg-gray-100 border-gray-500", "Draft", "The prescription is not yet 'actionable', e.g. it is a finished prescription for which the clinician has not yet added the dosage instructions.")),
+            "unknown" => Some(Chip::new("bg-gray-100 border-gray-500", "Unknown", "The authoring/source system does not know which of the status values currently applies for this request. Note: This concept is not to be used for 'other' - one of the listed statuses is presumed to apply, but the authoring/source system does not know which.")),
+            _ => None,
+        }
+    }
+
+    pub fn intent(&self) -> String {
+        self.intent.clone()
+    }
+
+    pub fn medication<'a>(&'a self, bundle: &'a MixedBundle) -> (String, Option<&'a Medication>) {
+        medication(
+            &self.medication_codeable_concept,
+            &self.medication_reference,
+            bundle,
+        )
+    }
+
+    pub fn dosage(&self) -> String {
+        self.dosage_instruction
+            .iter()
+            .flatten()
+            .map(|dosage| dosage.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl TimelineEvent for MedicationRequest {
+    fn timestamp(&self) -> Option<DateTime> {
+        self.authored_on
+    }
+}
+
 pub trait TimelineEvent {
     /// Returns the timestamp that is used to sort events in the timeline. If
     /// `None` is returned, the event will not be included in the timeline.
-    fn timestamp(&self) -> Option<jiff::Timestamp>;
+    fn timestamp(&self) -> Option<DateTime>;
 
     fn formatted_timestamp(&self) -> String {
         self.timestamp()
-            .map(format_time)
+            .map(|timestamp| timestamp.to_string())
             .unwrap_or_else(|| "Unknown".to_string())
     }
 }
 
+/// Explains why the FHIR server rejected a request.
+///
+/// http://hl7.org/fhir/R4/operationoutcome.html
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OperationOutcome {
+    #[serde(default)]
+    pub issue: Vec<OperationOutcomeIssue>,
+}
+
+/// http://hl7.org/fhir/R4/operationoutcome-definitions.html#OperationOutcome.issue
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OperationOutcomeIssue {
+    /// `fatal`, `error`, `warning` or `information`
+    pub severity: String,
+    /// http://hl7.org/fhir/R4/valueset-issue-type.html
+    pub code: String,
+    pub details: Option<CodeableConcept>,
+    pub diagnostics: Option<String>,
+    /// FHIRPath expressions of the elements the issue is about
+    pub expression: Option<Vec<String>>,
+}
+
+impl OperationOutcomeIssue {
+    /// http://hl7.org/fhir/R4/valueset-issue-severity.html
+    pub fn severity_chip(&self) -> Option<Chip> {
+        match self.severity.as_str() {
+            "fatal" => Some(Chip::new("bg-red-100 border-red-500", "Fatal", "The issue caused the action to fail and no further checking could be performed.")),
+            "error" => Some(Chip::new("bg-red-100 border-red-500", "Error", "The issue is sufficiently important to cause the action to fail.")),
+            "warning" => Some(Chip::new("bg-yellow-100 border-yellow-500", "Warning", "The issue is not important enough to cause the action to fail but may cause it to be performed suboptimally or in a way that is not as desired.")),
+            "information" => Some(Chip::new("bg-blue-100 border-blue-500", "Information", "The issue has no relation to the degree of success of the action.")),
+            _ => None,
+        }
+    }
+
+    /// The human readable description of the issue, falling back to its code.
+    pub fn message(&self) -> String {
+        match self.details {
+            Some(ref details) if !details.to_string().is_empty() => details.to_string(),
+            _ => self.code.clone(),
+        }
+    }
+
+    pub fn expression(&self) -> String {
+        self.expression.iter().flatten().join(", ")
+    }
+}
+
+impl fmt::Display for OperationOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issues = self.issue.iter().map(|issue| match issue.diagnostics {
+            Some(ref diagnostics) => format!("{}: {diagnostics}", issue.message()),
+            None => issue.message(),
+        });
+        write!(f, "{}", issues.format("; "))
+    }
+}
+
+#[cfg(feature = "server")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FhirEntry<T> {
     pub resource: T,
 }
 
+/// http://hl7.org/fhir/StructureDefinition/Bundle#Bundle.link
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleLink {
+    pub relation: String,
+    pub url: String,
+}
+
+#[cfg(feature = "server")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FhirBundle<T> {
+    pub total: Option<u32>,
+    pub link: Option<Vec<BundleLink>>,
+    // An empty searchset has no entry element at all
+    #[serde(default = "Vec::new")]
     pub entry: Vec<FhirEntry<T>>,
 }
 
+#[cfg(feature = "server")]
+impl<T> FhirBundle<T> {
+    /// Returns the URL of the link with the given relation, e.g. `next`, `previous` or `self`.
+    pub fn link(&self, relation: &str) -> Option<&str> {
+        self.link
+            .iter()
+            .flatten()
+            .find(|link| link.relation == relation)
+            .map(|link| link.url.as_str())
+    }
+}
+
+// Resources are only held in bundles, so boxing the larger ones buys nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "resourceType")]
 pub enum Resource {
@@ -512,31 +1929,252 @@ pub enum Resource {
     Encounter(Encounter),
     Condition(Condition),
     Procedure(Procedure),
-    #[serde(other)]
-    Unknown,
+    Observation(Observation),
+    Medication(Medication),
+    MedicationStatement(MedicationStatement),
+    MedicationAdministration(MedicationAdministration),
+    MedicationRequest(MedicationRequest),
+    // Not FHIR resource types, those never start with an underscore
+    #[serde(rename = "_Invalid")]
+    Invalid(InvalidResource),
+    #[serde(rename = "_Other")]
+    Other(OtherResource),
+}
+
+/// The resource types that have a variant in [`Resource`]. Resources of other
+/// types become [`Resource::Other`].
+const MODELLED_RESOURCE_TYPES: &[&str] = &[
+    "Patient",
+    "Encounter",
+    "Condition",
+    "Procedure",
+    "Observation",
+    "Medication",
+    "MedicationStatement",
+    "MedicationAdministration",
+    "MedicationRequest",
+    "_Invalid",
+    "_Other",
+];
+
+/// A resource of a known type that does not match its model, e.g. because a
+/// required element is missing. It is kept as JSON, so the rest of the bundle
+/// can still be shown.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InvalidResource {
+    pub error: String,
+    pub json: serde_json::Value,
+}
+
+impl InvalidResource {
+    pub fn resource_type(&self) -> String {
+        self.json["resourceType"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    pub fn id(&self) -> String {
+        self.json["id"].as_str().unwrap_or_default().to_string()
+    }
+}
+
+/// A resource of a type that Scout does not model, e.g. a `Specimen`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OtherResource {
+    pub json: serde_json::Value,
+}
+
+impl OtherResource {
+    pub fn resource_type(&self) -> String {
+        self.json["resourceType"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    pub fn id(&self) -> String {
+        self.json["id"].as_str().unwrap_or_default().to_string()
+    }
+}
+
+impl TimelineEvent for OtherResource {
+    /// Best effort, since the elements differ between resource types: the
+    /// clinically relevant `effective[x]` or `date` if there is one, otherwise
+    /// the time the resource was stored.
+    fn timestamp(&self) -> Option<DateTime> {
+        let json = &self.json;
+        [
+            &json["effectiveDateTime"],
+            &json["effectiveInstant"],
+            &json["effectivePeriod"]["start"],
+            &json["date"],
+            &json["meta"]["lastUpdated"],
+        ]
+        .into_iter()
+        .find_map(|value| value.as_str()?.parse().ok())
+    }
 }
 
 impl Resource {
+    /// The resource type and ID, or `None` if the resource has no ID.
+    pub fn type_and_id(&self) -> Option<(String, String)> {
+        let (resource_type, id) = match self {
+            Resource::Patient(patient) => ("Patient".to_string(), patient.id()),
+            Resource::Encounter(encounter) => ("Encounter".to_string(), encounter.id()),
+            Resource::Condition(condition) => ("Condition".to_string(), condition.id()),
+            Resource::Procedure(procedure) => ("Procedure".to_string(), procedure.id()),
+            Resource::Observation(observation) => ("Observation".to_string(), observation.id()),
+            Resource::Medication(medication) => ("Medication".to_string(), medication.id()),
+            Resource::MedicationStatement(statement) => {
+                ("MedicationStatement".to_string(), statement.id())
+            }
+            Resource::MedicationAdministration(administration) => {
+                ("MedicationAdministration".to_string(), administration.id())
+            }
+            Resource::MedicationRequest(request) => ("MedicationRequest".to_string(), request.id()),
+            Resource::Invalid(invalid) => (invalid.resource_type(), invalid.id()),
+            Resource::Other(other) => (other.resource_type(), other.id()),
+        };
+        (!id.is_empty()).then_some((resource_type, id))
+    }
+
+    /// The encounter the resource belongs to. For encounters this is the
+    /// encounter they are part of.
+    pub fn encounter(&self) -> Option<&Reference> {
+        match self {
+            Resource::Encounter(encounter) => encounter.part_of.as_ref(),
+            Resource::Condition(condition) => condition.encounter.as_ref(),
+            Resource::Procedure(procedure) => procedure.encounter.as_ref(),
+            _ => None,
+        }
+    }
+
     pub fn timeline_event(&self) -> Option<&dyn TimelineEvent> {
         match self {
             Resource::Encounter(encounter) => Some(encounter),
             Resource::Condition(condition) => Some(condition),
             Resource::Procedure(procedure) => Some(procedure),
+            Resource::Observation(observation) => Some(observation),
+            Resource::MedicationStatement(statement) => Some(statement),
+            Resource::MedicationAdministration(administration) => Some(administration),
+            Resource::MedicationRequest(request) => Some(request),
+            Resource::Other(other) => Some(other),
             _ => None,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawEntry")]
 pub struct MixedEntry {
     pub resource: Resource,
 }
 
+#[cfg(feature = "server")]
+impl MixedEntry {
+    /// Parses a resource like a `Bundle.entry.resource`.
+    pub fn from_resource(resource: serde_json::Value) -> Self {
+        RawEntry { resource }.into()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawEntry {
+    resource: serde_json::Value,
+}
+
+/// Parses each resource on its own, so that a malformed resource becomes
+/// [`Resource::Invalid`] instead of failing the whole bundle, and resources
+/// without a model are kept as [`Resource::Other`].
+impl From<RawEntry> for MixedEntry {
+    fn from(entry: RawEntry) -> Self {
+        let modelled = entry.resource["resourceType"]
+            .as_str()
+            .is_some_and(|resource_type| MODELLED_RESOURCE_TYPES.contains(&resource_type));
+        if !modelled {
+            return MixedEntry {
+                resource: Resource::Other(OtherResource {
+                    json: entry.resource,
+                }),
+            };
+        }
+        let resource = match Resource::deserialize(&entry.resource) {
+            Ok(resource) => resource,
+            Err(e) => Resource::Invalid(InvalidResource {
+                error: e.to_string(),
+                json: entry.resource,
+            }),
+        };
+        MixedEntry { resource }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MixedBundle {
     pub entry: Vec<MixedEntry>,
 }
 
+impl MixedBundle {
+    /// Looks up the `Condition` a reference like `Condition/123` points to.
+    pub fn condition(&self, reference: &Reference) -> Option<&Condition> {
+        let id = reference.id_of("Condition")?;
+        self.entry.iter().find_map(|entry| match entry.resource {
+            Resource::Condition(ref condition) if condition.id.as_deref() == Some(id) => {
+                Some(condition)
+            }
+            _ => None,
+        })
+    }
+
+    /// The secondary codes of a condition, i.e. the conditions that refer to
+    /// it with the `condition-related` extension.
+    pub fn secondary_conditions(&self, condition_id: &str) -> Vec<&Condition> {
+        self.entry
+            .iter()
+            .filter_map(|entry| match entry.resource {
+                Resource::Condition(ref condition) => Some(condition),
+                _ => None,
+            })
+            .filter(|condition| {
+                condition
+                    .related_condition()
+                    .and_then(|reference| reference.id_of("Condition"))
+                    == Some(condition_id)
+            })
+            .collect()
+    }
+
+    /// The encounters that list a condition as one of their diagnoses.
+    pub fn encounters_with_diagnosis(&self, condition_id: &str) -> Vec<&Encounter> {
+        self.entry
+            .iter()
+            .filter_map(|entry| match entry.resource {
+                Resource::Encounter(ref encounter) => Some(encounter),
+                _ => None,
+            })
+            .filter(|encounter| {
+                encounter
+                    .diagnosis
+                    .iter()
+                    .flatten()
+                    .any(|diagnosis| diagnosis.condition.id_of("Condition") == Some(condition_id))
+            })
+            .collect()
+    }
+
+    /// Looks up the `Medication` a reference like `Medication/123` points to.
+    pub fn medication(&self, reference: &Reference) -> Option<&Medication> {
+        let id = reference.id_of("Medication")?;
+        self.entry.iter().find_map(|entry| match entry.resource {
+            Resource::Medication(ref medication) if medication.id.as_deref() == Some(id) => {
+                Some(medication)
+            }
+            _ => None,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Chip {
     pub class: String,
@@ -553,3 +2191,168 @@ impl Chip {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_partial_dates() {
+        assert_eq!("2020".parse(), Ok(Date::Year(2020)));
+        assert_eq!("2020-03".parse(), Ok(Date::YearMonth(2020, 3)));
+        assert_eq!(
+            "2020-03-15".parse(),
+            Ok(Date::Day(jiff::civil::date(2020, 3, 15)))
+        );
+        for invalid in [
+            "20",
+            "2020-3",
+            "2020-13",
+            "2020-02-30",
+            "2020-03-15-01",
+            "abcd",
+            "",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{invalid}");
+        }
+        assert_eq!(String::from(Date::YearMonth(2020, 3)), "2020-03");
+    }
+
+    #[test]
+    fn parses_date_times() {
+        assert_eq!(
+            "2020-03".parse(),
+            Ok(DateTime::Date(Date::YearMonth(2020, 3)))
+        );
+        assert_eq!(
+            "2020-03-15T10:00:00+01:00".parse(),
+            Ok(DateTime::Instant("2020-03-15T09:00:00Z".parse().unwrap()))
+        );
+        // FHIR requires a time zone if a time is given
+        assert!("2020-03-15T10:00:00".parse::<DateTime>().is_err());
+        let date_time: DateTime = serde_json::from_str("\"2020-03-15\"").unwrap();
+        assert_eq!(serde_json::to_string(&date_time).unwrap(), "\"2020-03-15\"");
+    }
+
+    #[test]
+    fn places_dates_in_utc() {
+        let date: DateTime = "2020-03-15".parse().unwrap();
+        assert_eq!(date.start(), "2020-03-15T00:00:00Z".parse().unwrap());
+        let year: DateTime = "2020".parse().unwrap();
+        assert_eq!(year.start(), "2020-01-01T00:00:00Z".parse().unwrap());
+    }
+
+    #[test]
+    fn shows_the_marker_of_each_icd_coding() {
+        let marker = |code| {
+            json!([{
+                "url": "http://fhir.de/StructureDefinition/icd-10-gm-mehrfachcodierungs-kennzeichen",
+                "valueCoding": {"code": code}
+            }])
+        };
+        let condition: Condition = serde_json::from_value(json!({
+            "resourceType": "Condition",
+            "subject": {"reference": "Patient/1"},
+            "recordedDate": "2020-03-15",
+            "code": {"coding": [
+                {"system": ICD_10_GM, "code": "A54.4", "extension": marker("†")},
+                {"system": ICD_10_GM, "code": "M73.09", "extension": marker("*")},
+                {"system": ICD_10_GM, "code": "U69.40"}
+            ]}
+        }))
+        .unwrap();
+        assert_eq!(condition.code(), "A54.4†, M73.09*, U69.40");
+    }
+
+    #[test]
+    fn keeps_extensions_and_absent_reasons() {
+        let absent = json!({"extension": [{"url": DATA_ABSENT_REASON, "valueCode": "masked"}]});
+        let patient: Patient = serde_json::from_value(json!({
+            "resourceType": "Patient",
+            "extension": [
+                {"url": "http://example.org/rank", "valuePositiveInt": 2},
+                {"url": "http://example.org/range", "valueRange": {
+                    "low": {"value": 1, "unit": "kg"},
+                    "high": {"value": 2, "unit": "kg"}
+                }},
+                {"url": "http://example.org/time", "valueTime": "10:30:00"}
+            ],
+            "name": [{"given": ["Max", null], "_given": [null, absent], "family": "Mustermann"}],
+            "_deceasedBoolean": absent,
+            "address": [{"_line": [absent], "city": "Berlin"}]
+        }))
+        .unwrap();
+        let values = patient
+            .extensions()
+            .iter()
+            .map(|extension| extension.value.as_ref().unwrap().to_string())
+            .collect_vec();
+        assert_eq!(values, ["2", "1 kg – 2 kg", "10:30:00"]);
+        assert_eq!(patient.name(), "Max unknown (masked) Mustermann");
+        assert_eq!(patient.deceased(), "unknown (masked)");
+        assert_eq!(patient.address(), "unknown (masked), Berlin");
+    }
+
+    #[test]
+    fn keeps_extensions_of_nested_datatypes() {
+        let statement: MedicationStatement = serde_json::from_value(json!({
+            "resourceType": "MedicationStatement",
+            "status": "active",
+            "dosage": [{
+                "text": "1 tablet",
+                "doseAndRate": [{
+                    "extension": [{"url": "http://example.org/dose-note", "valueString": "with food"}],
+                    "doseQuantity": {"value": 1, "unit": "tablet"}
+                }]
+            }]
+        }))
+        .unwrap();
+        let round_trip: MedicationStatement =
+            serde_json::from_value(serde_json::to_value(&statement).unwrap()).unwrap();
+        for statement in [statement, round_trip] {
+            let dose_and_rate = &statement.dosage.as_ref().unwrap()[0]
+                .dose_and_rate
+                .as_ref()
+                .unwrap()[0];
+            assert_eq!(
+                dose_and_rate
+                    .extension("http://example.org/dose-note")
+                    .and_then(|extension| extension.value.as_ref())
+                    .map(ToString::to_string)
+                    .as_deref(),
+                Some("with food")
+            );
+        }
+    }
+
+    #[test]
+    fn orders_by_start_then_precision() {
+        let values = [
+            "2020-03-15T10:00:00Z",
+            "2020-03-15",
+            "2019-12-31T23:00:00-02:00",
+            "2020-03",
+            "2020",
+            "2020-01-01T00:00:00Z",
+        ]
+        .map(|s| s.parse::<DateTime>().unwrap());
+        let sorted = values
+            .iter()
+            .copied()
+            .sorted()
+            .map(String::from)
+            .collect_vec();
+        assert_eq!(
+            sorted,
+            [
+                "2020",
+                "2020-01-01T00:00:00Z",
+                "2020-01-01T01:00:00Z",
+                "2020-03",
+                "2020-03-15",
+                "2020-03-15T10:00:00Z",
+            ]
+        );
+    }
+}
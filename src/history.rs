@@ -0,0 +1,245 @@
+//! Filters for the history of a patient, which is fetched with
+//! `Patient/$everything`.
+
+use std::fmt;
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Resource types that can be selected in the filter form.
+const RESOURCE_TYPES: [&str; 7] = [
+    "Encounter",
+    "Condition",
+    "Procedure",
+    "Observation",
+    "MedicationStatement",
+    "MedicationAdministration",
+    "MedicationRequest",
+];
+
+/// The filter of the patient view. It is part of the URL, so that views like
+/// "conditions from 2023" can be shared, and is translated into parameters of
+/// `$everything` on the server. Empty fields are ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HistoryFilter {
+    /// Comma separated resource types, all if empty
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub types: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<jiff::civil::Date>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<jiff::civil::Date>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_since: Option<jiff::civil::Date>,
+}
+
+impl HistoryFilter {
+    fn has_type(&self, resource_type: &str) -> bool {
+        self.types.split(',').any(|t| t == resource_type)
+    }
+
+    fn toggle_type(&mut self, resource_type: &str) {
+        let types = if self.has_type(resource_type) {
+            self.types
+                .split(',')
+                .filter(|t| !t.is_empty() && *t != resource_type)
+                .collect::<Vec<_>>()
+        } else {
+            self.types
+                .split(',')
+                .filter(|t| !t.is_empty())
+                .chain([resource_type])
+                .collect()
+        };
+        self.types = types.join(",");
+    }
+}
+
+#[cfg(feature = "server")]
+impl HistoryFilter {
+    /// http://hl7.org/fhir/R4/patient-operation-everything.html
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if !self.types.is_empty() {
+            // The patient is needed to check whether the user may see them
+            params.push(("_type", format!("Patient,{}", self.types)));
+        }
+        if let Some(date) = self.start {
+            params.push(("start", date.to_string()));
+        }
+        if let Some(date) = self.end {
+            params.push(("end", date.to_string()));
+        }
+        if let Some(date) = self.changed_since {
+            params.push(("_since", format!("{date}T00:00:00Z")));
+        }
+        params
+    }
+
+    /// Whether a resource was changed since `changed_since` according to its
+    /// `meta.lastUpdated`. Not every FHIR server supports `_since`, so it is
+    /// applied again to what it returns. The patient and resources without
+    /// `meta.lastUpdated` are always kept.
+    pub fn changed(&self, resource: &serde_json::Value) -> bool {
+        let Some(since) = self.changed_since else {
+            return true;
+        };
+        if resource["resourceType"] == "Patient" {
+            return true;
+        }
+        resource["meta"]["lastUpdated"]
+            .as_str()
+            .and_then(|time| time.parse::<jiff::Timestamp>().ok())
+            .is_none_or(|time| time.to_zoned(jiff::tz::TimeZone::UTC).date() >= since)
+    }
+
+    /// Whether an entry of the timeline overlaps `start` to `end`, so that
+    /// e.g. an event in `2023` is kept for a filter from March 2023. Like
+    /// `_since`, these parameters of `$everything` are applied again to what
+    /// the server returns. Entries without a timestamp are always kept.
+    pub fn includes(&self, entry: &crate::fhir::MixedEntry) -> bool {
+        let Some(date) = entry
+            .resource
+            .timeline_event()
+            .and_then(|event| event.timestamp())
+            .map(|time| time.date())
+        else {
+            return true;
+        };
+        self.start.is_none_or(|start| start <= date.last_day())
+            && self.end.is_none_or(|end| date.first_day() <= end)
+    }
+}
+
+impl fmt::Display for HistoryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_urlencoded::to_string(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+impl From<&str> for HistoryFilter {
+    fn from(query: &str) -> Self {
+        serde_urlencoded::from_str(query).unwrap_or_default()
+    }
+}
+
+#[component]
+pub fn HistoryFilterForm(filter: HistoryFilter, onfilter: EventHandler<HistoryFilter>) -> Element {
+    let mut form = use_signal(|| filter.clone());
+    // Follow the URL, e.g. when navigating back to a previous filter
+    use_effect(use_reactive!(|filter| form.set(filter)));
+    let input_class = "border border-gray-300 rounded p-1";
+    rsx! {
+        form {
+            class: "my-3 flex flex-wrap items-end gap-3",
+            onsubmit: move |event| {
+                event.prevent_default();
+                onfilter(form());
+            },
+            fieldset {
+                class: "flex flex-wrap gap-2 text-sm",
+                legend { "Resource types" }
+                for resource_type in RESOURCE_TYPES {
+                    label {
+                        class: "flex items-center gap-1",
+                        input {
+                            r#type: "checkbox",
+                            checked: form.read().has_type(resource_type),
+                            onchange: move |_| form.write().toggle_type(resource_type),
+                        }
+                        "{resource_type}"
+                    }
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "From"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().start.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().start = event.value().parse().ok(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Until"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().end.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().end = event.value().parse().ok(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Changed since"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().changed_since.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().changed_since = event.value().parse().ok(),
+                }
+            }
+            button { class: "border border-gray-300 rounded px-3 py-1", r#type: "submit", "Filter" }
+            button {
+                class: "border border-gray-300 rounded px-3 py-1",
+                r#type: "button",
+                onclick: move |_| {
+                    form.set(HistoryFilter::default());
+                    onfilter(HistoryFilter::default());
+                },
+                "Reset"
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::fhir::MixedEntry;
+    use serde_json::json;
+
+    fn filter(query: &str) -> HistoryFilter {
+        HistoryFilter::from(query)
+    }
+
+    fn condition(recorded: &str) -> MixedEntry {
+        MixedEntry::from_resource(json!({
+            "resourceType": "Condition",
+            "subject": {"reference": "Patient/1"},
+            "code": {"text": "Fever"},
+            "recordedDate": recorded
+        }))
+    }
+
+    #[test]
+    fn includes_partial_dates_that_overlap() {
+        let march = filter("start=2023-03-01&end=2023-03-31");
+        assert!(march.includes(&condition("2023")));
+        assert!(march.includes(&condition("2023-03")));
+        assert!(march.includes(&condition("2023-03-31")));
+        assert!(!march.includes(&condition("2023-02")));
+        assert!(!march.includes(&condition("2023-04-01")));
+        assert!(!march.includes(&condition("2022")));
+    }
+
+    #[test]
+    fn rechecks_changed_since() {
+        let since = filter("changed-since=2024-01-01");
+        let observation = |last_updated: &str| json!({"resourceType": "Observation", "meta": {"lastUpdated": last_updated}});
+        assert!(since.changed(&observation("2024-01-01T00:00:00Z")));
+        assert!(!since.changed(&observation("2023-12-31T23:59:59Z")));
+        assert!(since.changed(&json!({"resourceType": "Observation"})));
+        assert!(since.changed(&json!({
+            "resourceType": "Patient",
+            "meta": {"lastUpdated": "2020-01-01T00:00:00Z"}
+        })));
+    }
+}
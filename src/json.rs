@@ -0,0 +1,252 @@
+//! Collapsible tree view of raw FHIR JSON and helpers to inspect it.
+
+use dioxus::prelude::*;
+use serde_json::Value;
+
+/// Objects and arrays deeper than this start out collapsed.
+const OPEN_DEPTH: usize = 2;
+
+#[component]
+pub fn JsonTree(value: Value) -> Element {
+    rsx! {
+        div {
+            class: "font-mono text-xs overflow-x-auto",
+            JsonNode { name: None, value, depth: 0 }
+        }
+    }
+}
+
+#[component]
+fn JsonNode(name: Option<String>, value: Value, depth: usize) -> Element {
+    let label = rsx! {
+        if let Some(name) = name {
+            span { class: "text-purple-700", "{name}: " }
+        }
+    };
+    let children = match value {
+        Value::Object(ref object) => Some((
+            format!("{{{}}}", object.len()),
+            object
+                .iter()
+                .map(|(key, value)| (format!("\"{key}\""), value.clone()))
+                .collect::<Vec<_>>(),
+        )),
+        Value::Array(ref values) => Some((
+            format!("[{}]", values.len()),
+            values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| (index.to_string(), value.clone()))
+                .collect(),
+        )),
+        _ => None,
+    };
+    match children {
+        Some((summary, children)) => rsx! {
+            details {
+                open: depth < OPEN_DEPTH,
+                summary {
+                    class: "cursor-pointer",
+                    {label}
+                    span { class: "text-gray-500", "{summary}" }
+                }
+                div {
+                    class: "ms-4",
+                    for (name, value) in children {
+                        JsonNode { name: Some(name), value, depth: depth + 1 }
+                    }
+                }
+            }
+        },
+        None => {
+            let class = match value {
+                Value::String(_) => "text-green-700",
+                Value::Number(_) => "text-blue-700",
+                Value::Bool(_) => "text-orange-700",
+                _ => "text-gray-500",
+            };
+            rsx! {
+                div {
+                    {label}
+                    span { class, "{value}" }
+                }
+            }
+        }
+    }
+}
+
+/// Whether a resource contains a reference to `target`, e.g. `Patient/123`.
+pub fn references(resource: &Value, target: &str) -> bool {
+    match resource {
+        Value::Object(object) => object.iter().any(|(key, value)| {
+            key == "reference"
+                && value.as_str().is_some_and(|reference| {
+                    reference == target || reference.ends_with(&format!("/{target}"))
+                })
+                || references(value, target)
+        }),
+        Value::Array(values) => values.iter().any(|value| references(value, target)),
+        _ => false,
+    }
+}
+
+/// A difference between two JSON values at `path`, e.g. `code.coding[0].code`.
+/// `old` is `None` for added and `new` for removed elements.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    path: String,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+/// Compares two JSON values structurally. Objects are compared key by key and
+/// arrays element by element.
+fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at(String::new(), Some(old), Some(new), &mut changes);
+    changes
+}
+
+fn diff_at(path: String, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<Change>) {
+    let child = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+    match (old, new) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            for key in old
+                .keys()
+                .chain(new.keys().filter(|key| !old.contains_key(*key)))
+            {
+                diff_at(child(key), old.get(key), new.get(key), changes);
+            }
+        }
+        (Some(Value::Array(old)), Some(Value::Array(new))) => {
+            for index in 0..old.len().max(new.len()) {
+                diff_at(
+                    format!("{path}[{index}]"),
+                    old.get(index),
+                    new.get(index),
+                    changes,
+                );
+            }
+        }
+        (old, new) if old != new => changes.push(Change {
+            path,
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+/// List of the changes between two versions of a resource.
+#[component]
+pub fn JsonDiff(old: Value, new: Value) -> Element {
+    let changes = diff(&old, &new);
+    rsx! {
+        div {
+            class: "font-mono text-xs overflow-x-auto",
+            if changes.is_empty() {
+                p { class: "font-sans text-sm", "The versions are identical." }
+            }
+            for change in changes {
+                div {
+                    span { class: "text-purple-700", "{change.path}: " }
+                    if let Some(old) = change.old {
+                        span { class: "bg-red-100 line-through", "{old}" }
+                    }
+                    if let Some(new) = change.new {
+                        span { class: "bg-green-100 ms-1", "{new}" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn change(path: &str, old: Option<Value>, new: Option<Value>) -> Change {
+        Change {
+            path: path.to_string(),
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn identical_values_have_no_changes() {
+        let value = json!({"status": "final", "code": {"coding": [{"code": "718-7"}]}});
+        assert_eq!(diff(&value, &value), Vec::new());
+        assert_eq!(diff(&json!(null), &json!(null)), Vec::new());
+    }
+
+    #[test]
+    fn compares_object_keys() {
+        let old = json!({"status": "preliminary", "issued": "2024-01-01", "code": {"text": "Hb"}});
+        let new = json!({"status": "final", "code": {"text": "Hb"}, "note": "checked"});
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                change("issued", Some(json!("2024-01-01")), None),
+                change("status", Some(json!("preliminary")), Some(json!("final"))),
+                change("note", None, Some(json!("checked"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_nested_values_by_path() {
+        let old = json!({"code": {"coding": [{"system": "http://loinc.org", "code": "718-7"}]}});
+        let new = json!({"code": {"coding": [{"system": "http://loinc.org", "code": "4548-4"}]}});
+        assert_eq!(
+            diff(&old, &new),
+            vec![change(
+                "code.coding[0].code",
+                Some(json!("718-7")),
+                Some(json!("4548-4"))
+            )]
+        );
+    }
+
+    #[test]
+    fn compares_arrays_element_by_element() {
+        let short = json!({"line": ["Main Street 1"]});
+        let long = json!({"line": ["Main Street 1", "Apartment 2", "Floor 3"]});
+        assert_eq!(
+            diff(&short, &long),
+            vec![
+                change("line[1]", None, Some(json!("Apartment 2"))),
+                change("line[2]", None, Some(json!("Floor 3"))),
+            ]
+        );
+        assert_eq!(
+            diff(&long, &short),
+            vec![
+                change("line[1]", Some(json!("Apartment 2")), None),
+                change("line[2]", Some(json!("Floor 3")), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_values_of_another_type() {
+        let old = json!({"value": {"value": 12.5}});
+        let new = json!({"value": [12.5]});
+        assert_eq!(
+            diff(&old, &new),
+            vec![change(
+                "value",
+                Some(json!({"value": 12.5})),
+                Some(json!([12.5]))
+            )]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Trend charts of numeric lab values, grouped by LOINC code.
+
+use dioxus::prelude::*;
+use itertools::Itertools;
+
+use crate::fhir::{self, TimelineEvent};
+use crate::OptionalChip;
+
+const WIDTH: f64 = 480.0;
+const HEIGHT: f64 = 180.0;
+/// Space for the axis labels on the left and at the bottom
+const MARGIN_LEFT: f64 = 48.0;
+const MARGIN_BOTTOM: f64 = 20.0;
+const MARGIN: f64 = 8.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabPoint {
+    pub time: fhir::DateTime,
+    pub value: f64,
+    /// `<`, `<=`, `>=` or `>` if the value is a detection limit rather than
+    /// the measured value
+    pub comparator: Option<String>,
+    pub abnormal: bool,
+    pub reference_range: String,
+    pub interpretation: Option<fhir::Chip>,
+}
+
+/// All numeric results of one analyte, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabSeries {
+    pub loinc: String,
+    pub name: String,
+    pub unit: String,
+    /// Reference range of the most recent result that has one
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    pub points: Vec<LabPoint>,
+}
+
+/// Collects the Observations with a LOINC code, a timestamp and a numeric
+/// value from a bundle. A result is abnormal if it is interpreted as anything
+/// but normal or lies outside its reference range.
+pub fn lab_series(bundle: &fhir::MixedBundle) -> Vec<LabSeries> {
+    bundle
+        .entry
+        .iter()
+        .filter_map(|entry| match entry.resource {
+            fhir::Resource::Observation(ref observation) => Some(observation),
+            _ => None,
+        })
+        .filter_map(|observation| {
+            let loinc = observation.code.code_in_system("http://loinc.org")?;
+            let time = observation.timestamp()?;
+            let quantity = observation.value_quantity.as_ref()?;
+            Some((loinc, time, quantity.value?, observation))
+        })
+        .into_group_map_by(|(loinc, ..)| loinc.clone())
+        .into_iter()
+        .map(|(loinc, mut results)| {
+            results.sort_by_key(|(_, time, ..)| *time);
+            let (_, _, _, latest) = results.last().unwrap();
+            let (low, high) = results
+                .iter()
+                .rev()
+                .find_map(|(.., observation)| {
+                    let range = observation.reference_range.as_ref()?.first()?;
+                    let low = range.low.as_ref().and_then(|low| low.value);
+                    let high = range.high.as_ref().and_then(|high| high.value);
+                    (low.is_some() || high.is_some()).then_some((low, high))
+                })
+                .unwrap_or_default();
+            LabSeries {
+                name: latest.code(),
+                unit: latest
+                    .value_quantity
+                    .as_ref()
+                    .and_then(|quantity| quantity.unit.clone().or(quantity.code.clone()))
+                    .unwrap_or_default(),
+                low,
+                high,
+                points: results
+                    .iter()
+                    .map(|(_, time, value, observation)| {
+                        let comparator = observation
+                            .value_quantity
+                            .as_ref()
+                            .and_then(|quantity| quantity.comparator.clone());
+                        // "<5" is known to be below a lower limit of 6, but
+                        // not known to be above an upper limit of 4
+                        let below_known = !matches!(comparator.as_deref(), Some(">" | ">="));
+                        let above_known = !matches!(comparator.as_deref(), Some("<" | "<="));
+                        let range = observation
+                            .reference_range
+                            .as_ref()
+                            .and_then(|ranges| ranges.first());
+                        let outside = range.is_some_and(|range| {
+                            range
+                                .low
+                                .as_ref()
+                                .and_then(|low| low.value)
+                                .is_some_and(|low| below_known && *value < low)
+                                || range
+                                    .high
+                                    .as_ref()
+                                    .and_then(|high| high.value)
+                                    .is_some_and(|high| above_known && *value > high)
+                        });
+                        LabPoint {
+                            time: *time,
+                            value: *value,
+                            comparator,
+                            abnormal: outside
+                                || observation
+                                    .interpretation_code()
+                                    .is_some_and(|code| code != "N"),
+                            reference_range: observation.reference_range(),
+                            interpretation: observation.interpretation_chip(),
+                        }
+                    })
+                    .collect(),
+                loinc,
+            }
+        })
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .collect()
+}
+
+#[component]
+pub fn Labs(series: Vec<LabSeries>) -> Element {
+    let mut show_table = use_signal(|| false);
+    rsx! {
+        div {
+            class: "flex items-center gap-3 my-3",
+            h2 { class: "text-xl font-bold", "Labs" }
+            button {
+                class: "border border-gray-300 rounded px-2",
+                onclick: move |_| show_table.set(!show_table()),
+                if show_table() { "Show charts" } else { "Show table" }
+            }
+        }
+        div {
+            class: "flex flex-wrap gap-4",
+            for series in series {
+                div {
+                    class: "border border-gray-300 rounded p-2",
+                    h3 {
+                        class: "font-bold",
+                        "{series.name} "
+                        span { class: "text-sm font-normal text-gray-600", "LOINC {series.loinc}" }
+                    }
+                    if show_table() {
+                        LabTable { series }
+                    } else {
+                        LabChart { series }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl LabPoint {
+    /// The value with its comparator, e.g. `<0.5`
+    fn value(&self) -> String {
+        format!(
+            "{}{}",
+            self.comparator.as_deref().unwrap_or_default(),
+            self.value
+        )
+    }
+}
+
+/// Formats an axis label with as many decimals as the span of the axis needs,
+/// so that floating point noise like `0.30000000000000004` doesn't show.
+fn axis_label(value: f64, span: f64) -> String {
+    let decimals = if span > 0.0 {
+        (1.0 - span.log10().floor()).clamp(0.0, 6.0) as usize
+    } else {
+        1
+    };
+    format!("{value:.decimals$}")
+}
+
+/// Line chart of the values over time. The reference range is shaded and
+/// abnormal results are drawn in red. Values beyond a detection limit, e.g.
+/// `<0.5`, are drawn at the limit as hollow circles.
+#[component]
+fn LabChart(series: LabSeries) -> Element {
+    let values = series.points.iter().map(|point| point.value);
+    let (min, max) = values
+        .chain(series.low)
+        .chain(series.high)
+        .minmax()
+        .into_option()
+        .unwrap_or((0.0, 1.0));
+    // Leave some room above and below, and give flat lines a height
+    let padding = if max > min { (max - min) * 0.1 } else { 1.0 };
+    let span = max - min;
+    let (min, max) = (min - padding, max + padding);
+    let y = |value: f64| MARGIN + (max - value) / (max - min) * (HEIGHT - MARGIN - MARGIN_BOTTOM);
+
+    let first = series
+        .points
+        .first()
+        .map(|point| point.time.start().as_second())
+        .unwrap_or_default();
+    let last = series
+        .points
+        .last()
+        .map(|point| point.time.start().as_second())
+        .unwrap_or_default();
+    let x = |time: fhir::DateTime| {
+        if last > first {
+            MARGIN_LEFT
+                + (time.start().as_second() - first) as f64 / (last - first) as f64
+                    * (WIDTH - MARGIN_LEFT - MARGIN)
+        } else {
+            (MARGIN_LEFT + WIDTH - MARGIN) / 2.0
+        }
+    };
+
+    let line = series
+        .points
+        .iter()
+        .map(|point| format!("{:.1},{:.1}", x(point.time), y(point.value)))
+        .join(" ");
+    let range = match (series.low, series.high) {
+        (None, None) => None,
+        (low, high) => {
+            let top = y(high.unwrap_or(max));
+            Some((top, y(low.unwrap_or(min)) - top))
+        }
+    };
+    let date = |point: Option<&LabPoint>| {
+        point
+            .map(|point| point.time.date().to_string())
+            .unwrap_or_default()
+    };
+
+    rsx! {
+        svg {
+            xmlns: "http://www.w3.org/2000/svg",
+            "viewBox": "0 0 {WIDTH} {HEIGHT}",
+            width: "{WIDTH}",
+            height: "{HEIGHT}",
+            if let Some((top, height)) = range {
+                rect {
+                    x: "{MARGIN_LEFT}",
+                    y: "{top:.1}",
+                    width: "{WIDTH - MARGIN_LEFT - MARGIN}",
+                    height: "{height:.1}",
+                    class: "fill-green-100",
+                }
+            }
+            line {
+                x1: "{MARGIN_LEFT}",
+                y1: "{HEIGHT - MARGIN_BOTTOM}",
+                x2: "{WIDTH - MARGIN}",
+                y2: "{HEIGHT - MARGIN_BOTTOM}",
+                class: "stroke-gray-300",
+            }
+            text { x: "{MARGIN_LEFT - 4.0}", y: "{y(max - padding):.1}", "text-anchor": "end", "dominant-baseline": "middle", class: "text-xs fill-gray-600", "{axis_label(max - padding, span)}" }
+            text { x: "{MARGIN_LEFT - 4.0}", y: "{y(min + padding):.1}", "text-anchor": "end", "dominant-baseline": "middle", class: "text-xs fill-gray-600", "{axis_label(min + padding, span)}" }
+            text { x: "{MARGIN_LEFT}", y: "{HEIGHT - 4.0}", class: "text-xs fill-gray-600", "{date(series.points.first())}" }
+            text { x: "{WIDTH - MARGIN}", y: "{HEIGHT - 4.0}", "text-anchor": "end", class: "text-xs fill-gray-600", "{date(series.points.last())}" }
+            polyline { points: "{line}", fill: "none", class: "stroke-gray-500" }
+            for point in series.points.iter() {
+                circle {
+                    cx: "{x(point.time):.1}",
+                    cy: "{y(point.value):.1}",
+                    r: if point.abnormal { "4" } else { "3" },
+                    class: match (point.abnormal, point.comparator.is_some()) {
+                        (true, true) => "fill-white stroke-red-500",
+                        (true, false) => "fill-red-500",
+                        (false, true) => "fill-white stroke-gray-500",
+                        (false, false) => "fill-gray-500",
+                    },
+                    title { "{point.time}: {point.value()} {series.unit}" }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn LabTable(series: LabSeries) -> Element {
+    rsx! {
+        div {
+            class: "grid gap-px p-px",
+            style: "grid-template-columns: repeat(4, auto)",
+            div {
+                class: "grid grid-cols-subgrid col-span-full",
+                for header in ["Time", "Value", "Reference range", "Interpretation"] {
+                    div { class: "outline outline-gray-300 p-2 bg-gray-100 font-bold", "{header}" }
+                }
+            }
+            for point in series.points.iter().rev() {
+                div {
+                    class: "grid grid-cols-subgrid col-span-full",
+                    div { class: "outline outline-gray-300 p-2", "{point.time}" }
+                    div {
+                        class: if point.abnormal { "outline outline-gray-300 p-2 text-red-600 font-bold" } else { "outline outline-gray-300 p-2" },
+                        "{point.value()} {series.unit}"
+                    }
+                    div { class: "outline outline-gray-300 p-2", "{point.reference_range}" }
+                    div { class: "outline outline-gray-300 p-2", OptionalChip { chip: point.interpretation.clone() } }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_labels_have_fixed_precision() {
+        assert_eq!(axis_label(0.1 + 0.2, 0.5), "0.30");
+        assert_eq!(axis_label(5.5, 4.0), "5.5");
+        assert_eq!(axis_label(140.25, 30.0), "140");
+        assert_eq!(axis_label(1000.0, 2000.0), "1000");
+        assert_eq!(axis_label(3.0, 0.0), "3.0");
+    }
+}
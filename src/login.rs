@@ -0,0 +1,118 @@
+//! Components for logging users in and out.
+
+use dioxus::prelude::*;
+
+use crate::server;
+use crate::Route;
+
+/// Layout for all routes that require a logged in user. Shows the landing
+/// page instead of the route if nobody is logged in.
+#[component]
+pub fn RequireLogin() -> Element {
+    let user = use_server_future(server::current_user)?;
+    let audit_log = use_server_future(server::can_view_audit_log)?;
+    match &*user.read_unchecked() {
+        Some(Ok(Some(user))) => rsx! {
+            header {
+                class: "flex items-center gap-3 px-4 py-2 border-b border-gray-300",
+                Link { class: "font-bold", to: Route::PatientTable { search: Default::default() }, "Scout" }
+                span { class: "ms-auto text-gray-600", "{user.name}" }
+                if matches!(*audit_log.read_unchecked(), Some(Ok(true))) {
+                    Link { class: "underline", to: Route::AuditLog {}, "Audit log" }
+                }
+                Link { class: "underline", to: Route::Logout {}, "Sign out" }
+            }
+            Outlet::<Route> {}
+        },
+        Some(Ok(None)) => rsx! { Login {} },
+        Some(Err(e)) => rsx! { "Error checking login: {e:#}" },
+        None => rsx! { "Loading..." },
+    }
+}
+
+/// Landing page for users that are not logged in.
+#[component]
+pub fn Login() -> Element {
+    let mut error = use_signal(|| None::<String>);
+    let route = use_route::<Route>();
+    rsx! {
+        div {
+            class: "m-4 flex flex-col items-start gap-3",
+            h1 { class: "text-2xl font-bold", "Scout" }
+            p { "Please sign in to browse patient data." }
+            button {
+                class: "border border-gray-300 rounded px-3 py-1",
+                onclick: move |_| {
+                    // Come back to the current page after the login, unless this is the login page itself
+                    let return_to = match route {
+                        Route::Login {} | Route::Logout {} | Route::LoginCallback { .. } => "/".to_string(),
+                        ref route => route.to_string(),
+                    };
+                    async move {
+                        match server::login_url(return_to).await {
+                            Ok(url) => {
+                                navigator().push(NavigationTarget::<Route>::External(url));
+                            }
+                            Err(e) => error.set(Some(e.to_string())),
+                        }
+                    }
+                },
+                "Sign in"
+            }
+            if let Some(error) = error() {
+                p { class: "text-red-600", "{error}" }
+            }
+        }
+    }
+}
+
+/// The redirect URL the identity provider sends the user back to.
+#[component]
+pub fn LoginCallback(code: String, state: String) -> Element {
+    let mut error = use_signal(|| None::<String>);
+    // Run the code exchange in the browser, so the session cookie is set on
+    // the server function response rather than the server-side render
+    use_effect(move || {
+        let code = code.clone();
+        let state = state.clone();
+        spawn(async move {
+            match server::complete_login(code, state).await {
+                Ok(return_to) => {
+                    navigator().replace(NavigationTarget::<Route>::from(return_to));
+                }
+                Err(e) => error.set(Some(e.to_string())),
+            }
+        });
+    });
+    rsx! {
+        div {
+            class: "m-4",
+            match error() {
+                Some(error) => rsx! {
+                    p { class: "text-red-600", "{error}" }
+                    Link { class: "underline", to: Route::Login {}, "Back to sign in" }
+                },
+                None => rsx! { "Signing in..." },
+            }
+        }
+    }
+}
+
+#[component]
+pub fn Logout() -> Element {
+    use_effect(|| {
+        spawn(async move {
+            match server::logout().await {
+                Ok(Some(url)) => {
+                    navigator().push(NavigationTarget::<Route>::External(url));
+                }
+                _ => {
+                    navigator().replace(Route::Login {});
+                }
+            }
+        });
+    });
+    rsx! {
+        div { class: "m-4", "Signing out..." }
+    }
+}
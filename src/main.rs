@@ -1,24 +1,70 @@
 use dioxus::prelude::*;
-use fhir::TimelineEvent;
-use itertools::Itertools;
 
+mod audit;
+mod error;
 mod fhir;
+mod history;
+mod json;
+mod labs;
+mod login;
+mod resource;
+mod search;
 mod server;
 mod table;
+mod timeline;
+
+use audit::AuditLog;
+use history::HistoryFilter;
+use login::{Login, LoginCallback, Logout, RequireLogin};
+use resource::ResourceView;
+use search::PatientSearch;
 
 #[derive(Debug, Clone, Routable, PartialEq)]
 #[rustfmt::skip]
 enum Route {
-    #[route("/")]
-    PatientTable {},
-    #[route("/patient/:id")]
-    PatientView { id: String },
+    #[route("/login")]
+    Login {},
+    #[route("/logout")]
+    Logout {},
+    #[route("/auth/callback?:code&:state")]
+    LoginCallback { code: String, state: String },
+    #[layout(RequireLogin)]
+        #[route("/?:..search")]
+        PatientTable { search: PatientSearch },
+        #[route("/patient/:id?:..filter")]
+        PatientView { id: String, filter: HistoryFilter },
+        #[route("/patient/:id/:resource_type/:resource_id")]
+        ResourceView { id: String, resource_type: String, resource_id: String },
+        #[route("/admin/audit")]
+        AuditLog {},
 }
 
 const TAILWIND_CSS: Asset = asset!("/assets/tailwind.css");
 
 fn main() {
-    dioxus::launch(App);
+    LaunchBuilder::new()
+        .with_context(server_only! {
+            server::FhirClient::from_env().expect("Invalid FHIR client configuration")
+        })
+        .with_context(server_only! {
+            server::Authentication::from_env().expect("Invalid authentication configuration")
+        })
+        .with_context(server_only! {
+            server::Policy::from_env().expect("Invalid access policy")
+        })
+        .with_context(server_only! {
+            server::AuditLog::from_env().expect("Invalid audit log configuration")
+        })
+        .with_context(server_only! {
+            server::ResponseCache::from_env().expect("Invalid cache configuration")
+        })
+        .with_context(server_only! {
+            server::Terminology::from_env().expect("Invalid terminology configuration")
+        })
+        .with_context(server_only! {
+            server::PageTokens::default()
+        })
+        .launch(App);
 }
 
 #[component]
@@ -29,36 +75,182 @@ fn App() -> Element {
     }
 }
 
+const PAGE_SIZES: [Option<u32>; 4] = [Some(25), Some(50), Some(100), None];
+
+/// The page of the patient table and the search it belongs to. Page tokens
+/// are only valid for their search, so a page of another search, e.g. after
+/// navigating back, counts as the first page.
+#[derive(Clone, Default)]
+struct Paging {
+    search: PatientSearch,
+    page: Option<String>,
+    /// Position of the first patient on the page, for the "showing X of N" line
+    offset: usize,
+}
+
+impl Paging {
+    fn page(&self, search: &PatientSearch) -> Option<String> {
+        self.page.clone().filter(|_| self.search == *search)
+    }
+
+    fn offset(&self, search: &PatientSearch) -> usize {
+        if self.search == *search {
+            self.offset
+        } else {
+            0
+        }
+    }
+}
+
 #[component]
-fn PatientTable() -> Element {
-    let patients = use_server_future(|| server::get_patients())?;
+fn PatientTable(search: PatientSearch) -> Element {
+    let mut paging = use_signal(Paging::default);
+    let mut count = use_signal(|| Some(50));
+    // Set by the refresh button to bypass the server's response cache once
+    let mut refresh = use_signal(|| false);
+    let mut patients = use_server_future(use_reactive!(|search| {
+        let (page, count) = (paging.read().page(&search), count());
+        server::search_patients(search, page, count, refresh.take())
+    }))?;
+    let mut reload = move || {
+        refresh.set(true);
+        patients.restart();
+    };
+    let form = rsx! {
+        search::PatientSearchForm {
+            search: search.clone(),
+            onsearch: move |search| {
+                paging.set(Paging::default());
+                navigator().push(Route::PatientTable { search });
+            }
+        }
+    };
     match &*patients.read_unchecked() {
-        Some(Ok(patients)) => rsx! {
-            table::Table {
-                headers: vec!["ID".to_string(), /*"Name".to_string(),*/ "Gender".to_string(), "Birth Date".to_string(), "Deceased".to_string(), "Address".to_string(), "".to_string()],
-                rows: patients.iter().map(|p| vec![p.id(), /*p.name(),*/ p.gender(), p.birth_date(), p.deceased(), p.address()]).collect(),
-                ondetail: move |id| {
-                    // Navigate to the patient view when a row is clicked
-                    navigator().push(Route::PatientView { id });
+        Some(Ok(patients)) => {
+            let next = patients.next.clone();
+            let previous = patients.previous.clone();
+            let shown = patients.items.len();
+            let truncated = patients.truncated;
+            let offset = paging.read().offset(&search);
+            let total = patients
+                .total
+                .map(|total| total.to_string())
+                .unwrap_or("?".into());
+            // Names are removed on the server unless the user may see them
+            let show_names = patients.items.iter().any(|p| !p.name().is_empty());
+            rsx! {
+                {form}
+                div {
+                    class: "m-4 flex items-center gap-3",
+                    span {
+                        if shown == 0 {
+                            "No patients found"
+                        } else {
+                            "Showing {offset + 1}–{offset + shown} of {total}"
+                        }
+                    }
+                    if truncated {
+                        span {
+                            class: "text-yellow-700",
+                            "Too many patients to show at once, narrow the search or choose a page size"
+                        }
+                    }
+                    button {
+                        class: "border border-gray-300 rounded px-2 disabled:text-gray-400",
+                        disabled: previous.is_none(),
+                        onclick: {
+                            let search = search.clone();
+                            move |_| paging.set(Paging {
+                                search: search.clone(),
+                                page: previous.clone(),
+                                offset: offset.saturating_sub(count().unwrap_or_default() as usize),
+                            })
+                        },
+                        "Previous"
+                    }
+                    button {
+                        class: "border border-gray-300 rounded px-2 disabled:text-gray-400",
+                        disabled: next.is_none(),
+                        onclick: {
+                            let search = search.clone();
+                            move |_| paging.set(Paging {
+                                search: search.clone(),
+                                page: next.clone(),
+                                offset: offset + shown,
+                            })
+                        },
+                        "Next"
+                    }
+                    button {
+                        class: "border border-gray-300 rounded px-2",
+                        onclick: move |_| reload(),
+                        "Refresh"
+                    }
+                    select {
+                        class: "border border-gray-300 rounded p-1",
+                        onchange: move |event| {
+                            // Page tokens encode the page size, so changing it starts from the first page
+                            count.set(event.value().parse().ok());
+                            paging.set(Paging::default());
+                        },
+                        for size in PAGE_SIZES {
+                            option {
+                                value: size.map(|size| size.to_string()).unwrap_or_default(),
+                                selected: size == count(),
+                                match size {
+                                    Some(size) => rsx! { "{size} per page" },
+                                    None => rsx! { "All" },
+                                }
+                            }
+                        }
+                    }
+                }
+                table::Table {
+                    headers: ["ID", "Name", "Gender", "Birth Date", "Deceased", "Address", ""].into_iter().filter(|header| show_names || *header != "Name").map(String::from).collect(),
+                    rows: patients.items.iter().map(|p| [Some(p.id()), show_names.then(|| p.name()), Some(p.gender()), Some(p.birth_date()), Some(p.deceased()), Some(p.address())].into_iter().flatten().collect()).collect(),
+                    ondetail: move |id| {
+                        // Navigate to the patient view when a row is clicked
+                        navigator().push(Route::PatientView { id, filter: HistoryFilter::default() });
+                    }
                 }
             }
+        }
+        Some(Err(ServerFnError::WrappedServerError(server::ServerError::Unavailable))) => rsx! {
+            {form}
+            RetryingNotice { onretry: move |_| patients.restart() }
+        },
+        Some(Err(e)) => rsx! {
+            {form}
+            error::ErrorView { title: "Error loading patients", error: e.clone() }
         },
-        Some(Err(e)) => rsx! { "Error loading patients: {e:#}" },
         None => rsx! { "Loading..." },
     }
 }
 
-fn now_until(timestamp: jiff::Timestamp) -> String {
-    let zoned = timestamp.to_zoned(jiff::tz::TimeZone::system());
-    let span = jiff::Zoned::now()
-        .until(
-            jiff::ZonedDifference::new(&zoned)
-                .smallest(jiff::Unit::Day)
-                .largest(jiff::Unit::Year),
-        )
-        // unwrap should be safe here, since both timestamps are in the same timezone
-        .unwrap();
-    format!("{span:#}")
+/// Seconds to wait before trying again while the FHIR server is unavailable
+const RETRY_DELAY: u32 = 5;
+
+/// Shown instead of an error while the FHIR server is unavailable. Calls
+/// `onretry` after a few seconds, so that the user does not have to. The
+/// browser has its own timers, the desktop and mobile renderers run on tokio.
+#[component]
+fn RetryingNotice(onretry: EventHandler) -> Element {
+    use_effect(move || {
+        spawn(async move {
+            let delay = std::time::Duration::from_secs(RETRY_DELAY.into());
+            #[cfg(feature = "web")]
+            gloo_timers::future::sleep(delay).await;
+            #[cfg(not(feature = "web"))]
+            tokio::time::sleep(delay).await;
+            onretry(());
+        });
+    });
+    rsx! {
+        p {
+            class: "m-4 p-2 border rounded bg-yellow-100 border-yellow-500",
+            "FHIR server unavailable, retrying in {RETRY_DELAY} seconds..."
+        }
+    }
 }
 
 #[component]
@@ -74,116 +266,205 @@ fn OptionalChip(chip: Option<fhir::Chip>) -> Element {
     }
 }
 
+/// Card for a resource that could not be parsed, so that it does not go
+/// unnoticed.
+#[component]
+fn InvalidResourceCard(patient_id: String, invalid: fhir::InvalidResource) -> Element {
+    let resource_id = invalid.id();
+    rsx! {
+        div {
+            class: "my-3 p-2 border rounded bg-yellow-100 border-yellow-500",
+            p {
+                class: "font-bold",
+                "{invalid.resource_type()}/{resource_id} could not be displayed"
+            }
+            p { class: "text-sm", "{invalid.error}" }
+            if resource_id.is_empty() {
+                details {
+                    summary { class: "text-sm underline cursor-pointer", "Raw resource" }
+                    json::JsonTree { value: invalid.json.clone() }
+                }
+            } else {
+                Link {
+                    class: "text-sm underline",
+                    to: Route::ResourceView { id: patient_id, resource_type: invalid.resource_type(), resource_id },
+                    "Raw resource"
+                }
+            }
+        }
+    }
+}
+
 #[component]
-fn PatientView(id: String) -> Element {
-    let id = use_signal(|| id);
-    let patient_details = use_server_future(move || server::get_patient_details(id()))?;
+fn PatientView(id: String, filter: HistoryFilter) -> Element {
+    let query = use_memo(use_reactive!(|(id, filter)| (id, filter)));
+    // The pages of the patient's history after the first one
+    let mut pages = use_signal(Vec::<server::Page<fhir::MixedEntry>>::new);
+    let mut loading = use_signal(|| false);
+    let mut load_error = use_signal(|| None::<String>);
+    // Set by the refresh button to bypass the server's response cache once
+    let mut refresh = use_signal(|| false);
+    // Whether the current pages were loaded with the refresh button, so that
+    // the following pages are fresh as well
+    let mut refreshed = use_signal(|| false);
+    // Pages of another patient or filter must not be mixed into the timeline
+    use_effect(move || {
+        query.read();
+        pages.write().clear();
+        load_error.set(None);
+        refreshed.set(false);
+    });
+    let mut patient_details = use_server_future(move || {
+        let (id, filter) = query();
+        server::get_patient_details(id, filter, refresh.take())
+    })?;
+    // Fetches the page after the last one that was loaded
+    let mut load_more = move || {
+        let next = match &*patient_details.read() {
+            Some(Ok((_, first))) => pages.read().last().unwrap_or(first).next.clone(),
+            _ => None,
+        };
+        let Some(token) = next else {
+            return;
+        };
+        if loading() {
+            return;
+        }
+        loading.set(true);
+        load_error.set(None);
+        spawn(async move {
+            let (id, filter) = query();
+            let page =
+                server::get_patient_history_page(id.clone(), filter.clone(), token, refreshed())
+                    .await;
+            if query() == (id, filter) {
+                match page {
+                    Ok(page) => pages.push(page),
+                    Err(e) => load_error.set(Some(error::message(&e))),
+                }
+            }
+            loading.set(false);
+        });
+    };
+    let form = rsx! {
+        history::HistoryFilterForm {
+            filter: query().1,
+            onfilter: move |filter| {
+                navigator().push(Route::PatientView { id: query().0, filter });
+            }
+        }
+    };
     match &*patient_details.read_unchecked() {
-        Some(Ok((patient, bundle))) => rsx! {
-            div {
-                class: "m-4",
-                h2 { class: "text-xl font-bold my-3", "Patient Details" }
-                // p { "Name: {patient.name()}" }
-                p { "Gender: {patient.gender()}" }
-                p { "Birth Date: {patient.birth_date()}" }
-                p { "Deceased: {patient.deceased()}" }
-                p { "Address: {patient.address()}" }
-                h2 { class: "text-xl font-bold my-3", "Patient Timeline" }
-                // p {
-                //     class: "flex gap-1.5",
-                //     svg {
-                //         stroke: "currentColor",
-                //         fill: "none",
-                //         xmlns: "http://www.w3.org/2000/svg",
-                //         "stroke-width": "1.5",
-                //         "viewBox": "0 0 24 24",
-                //         class: "size-6",
-                //         path {
-                //             "stroke-linejoin": "round",
-                //             "stroke-linecap": "round",
-                //             d: "M12 9v3.75m-9.303 3.376c-.866 1.5.217 3.374 1.948 3.374h14.71c1.73 0 2.813-1.874 1.948-3.374L13.949 3.378c-.866-1.5-3.032-1.5-3.898 0L2.697 16.126ZM12 15.75h.007v.008H12v-.008Z",
-                //         }
-                //     }
-                //     "4 events are not shown because they are missing a timestamp."
-                // }
-                ol { class: "relative border-s border-gray-300",
-                    for entry in bundle.entry.iter().filter(|e| e.resource.timeline_event().is_some()).sorted_by_key(|e| e.resource.timeline_event().unwrap().timestamp()) {
-                        li { class: "mb-5 ms-4",
-                            div { class: "absolute w-3 h-3 bg-gray-300 rounded-full mt-1.5 -start-1.5 border border-white" }
-                            match entry.resource {
-                                fhir::Resource::Encounter(ref encounter) => {
-                                    rsx! {
-                                        details {
-                                            open: false,
-                                            summary {
-                                                div {
-                                                    class: "inline-flex items-center gap-1.5",
-                                                    h3 { class: "font-bold", "Encounter" }
-                                                    OptionalChip { chip: encounter.status_chip() }
-                                                }
-                                            }
-                                            time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
-                                                "{encounter.formatted_timestamp()}"
-                                            }
-                                            p { "Class: {encounter.class()}" }
-                                            p { "Visit number: {encounter.visit_number()}" }
-                                            p { "Encounter level: {encounter.encounter_level()}" }
-                                            p { "Service type: {encounter.service_type()}" }
-                                            p { "Service provider: {encounter.service_provider()}" }
-                                        }
-                                    }
-                                }
-                                fhir::Resource::Condition(ref condition) => {
-                                    rsx! {
-                                        details {
-                                            open: true,
-                                            summary {
-                                                div {
-                                                    class: "inline-flex items-center gap-1.5",
-                                                    h3 { class: "font-bold", "Condition" }
-                                                    OptionalChip { chip: condition.clinical_status_chip() }
-                                                    OptionalChip { chip: condition.verification_status_chip() }
-                                                }
-                                            }
-                                            time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
-                                                "{condition.formatted_timestamp()}"
-                                            }
-                                            p { "Code: {condition.code()}" }
-                                            p { "Body site: {condition.body_site()}" }
-                                            p { "Onset: {condition.onset_start()}" }
-                                            // p { "Notes: {condition.notes()}" }
-                                        }
-                                    }
+        Some(Ok((patient, first))) => {
+            let (id, _) = query();
+            let more = pages.read().last().unwrap_or(first).next.is_some();
+            let bundle = &fhir::MixedBundle {
+                entry: first
+                    .items
+                    .iter()
+                    .chain(pages.read().iter().flat_map(|page| page.items.iter()))
+                    .cloned()
+                    .collect(),
+            };
+            let lab_series = labs::lab_series(bundle);
+            rsx! {
+                div {
+                    class: "m-4",
+                    div {
+                        class: "flex items-center gap-3 my-3",
+                        h2 { class: "text-xl font-bold", "Patient Details" }
+                        button {
+                            class: "border border-gray-300 rounded px-2",
+                            onclick: move |_| {
+                                pages.write().clear();
+                                load_error.set(None);
+                                refresh.set(true);
+                                refreshed.set(true);
+                                patient_details.restart();
+                            },
+                            "Refresh"
+                        }
+                    }
+                    if let Some(patient) = patient {
+                        if !patient.name().is_empty() {
+                            p { "Name: {patient.name()}" }
+                        }
+                        p { "Gender: {patient.gender()}" }
+                        p { "Birth Date: {patient.birth_date()}" }
+                        p { "Deceased: {patient.deceased()}" }
+                        p { "Address: {patient.address()}" }
+                    } else {
+                        // The Patient resource is shown as invalid below
+                        p { class: "text-gray-600", "The patient's details could not be displayed." }
+                    }
+                    {form}
+                    if !lab_series.is_empty() {
+                        labs::Labs { series: lab_series }
+                    }
+                    h2 { class: "text-xl font-bold my-3", "Patient Timeline" }
+                    // p {
+                    //     class: "flex gap-1.5",
+                    //     svg {
+                    //         stroke: "currentColor",
+                    //         fill: "none",
+                    //         xmlns: "http://www.w3.org/2000/svg",
+                    //         "stroke-width": "1.5",
+                    //         "viewBox": "0 0 24 24",
+                    //         class: "size-6",
+                    //         path {
+                    //             "stroke-linejoin": "round",
+                    //             "stroke-linecap": "round",
+                    //             d: "M12 9v3.75m-9.303 3.376c-.866 1.5.217 3.374 1.948 3.374h14.71c1.73 0 2.813-1.874 1.948-3.374L13.949 3.378c-.866-1.5-3.032-1.5-3.898 0L2.697 16.126ZM12 15.75h.007v.008H12v-.008Z",
+                    //         }
+                    //     }
+                    //     "4 events are not shown because they are missing a timestamp."
+                    // }
+                    for entry in bundle.entry.iter() {
+                        if let fhir::Resource::Invalid(ref invalid) = entry.resource {
+                            InvalidResourceCard { patient_id: id.clone(), invalid: invalid.clone() }
+                        }
+                    }
+                    {timeline::timeline(&id, bundle)}
+                    if more {
+                        // Loads the next page as soon as the end of the timeline is scrolled into view
+                        div {
+                            class: "my-3 flex items-center gap-3",
+                            onvisible: move |event| {
+                                if event.is_intersecting().unwrap_or_default() && load_error().is_none() {
+                                    load_more();
                                 }
-                                fhir::Resource::Procedure(ref procedure) => {
-                                    rsx! {
-                                        details {
-                                            open: true,
-                                            summary {
-                                                div {
-                                                    class: "inline-flex items-center gap-1.5",
-                                                    h3 { class: "font-bold", "Procedure" }
-                                                    OptionalChip { chip: procedure.status_chip() }
-                                                }
-                                            }
-                                            time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
-                                                "{procedure.formatted_timestamp()}"
-                                            }
-                                            p { "Category: {procedure.category()}" }
-                                            p { "Code: {procedure.code()}" }
-                                            p { "Body Site: {procedure.body_site()}" }
-                                            // p { "Notes: {procedure.note()}" }
-                                        }
-                                    }
+                            },
+                            if loading() {
+                                "Loading more history..."
+                            } else {
+                                button {
+                                    class: "border border-gray-300 rounded px-2",
+                                    onclick: move |_| load_more(),
+                                    "Load more history"
                                 }
-                                _ => unreachable!()
+                            }
+                            if let Some(error) = load_error() {
+                                span { class: "text-red-600", "Error loading more history: {error}" }
                             }
                         }
                     }
                 }
             }
+        }
+        Some(Err(ServerFnError::WrappedServerError(server::ServerError::Unavailable))) => rsx! {
+            div {
+                class: "m-4",
+                {form}
+                RetryingNotice { onretry: move |_| patient_details.restart() }
+            }
+        },
+        Some(Err(e)) => rsx! {
+            div {
+                class: "m-4",
+                {form}
+                error::ErrorView { title: "Error loading patient", error: e.clone() }
+            }
         },
-        Some(Err(e)) => rsx! { "Error loading patient: {e:#}" },
         None => rsx! { "Loading..." },
     }
 }
@@ -0,0 +1,137 @@
+//! Page showing the raw JSON of a single resource.
+
+use dioxus::prelude::*;
+
+use crate::error::ErrorView;
+use crate::fhir;
+use crate::history::HistoryFilter;
+use crate::json::{JsonDiff, JsonTree};
+use crate::server;
+use crate::Route;
+
+#[component]
+pub fn ResourceView(id: String, resource_type: String, resource_id: String) -> Element {
+    let resource = use_server_future(use_reactive!(|id, resource_type, resource_id| {
+        server::get_patient_resource(id, resource_type, resource_id)
+    }))?;
+    let file_name = format!("{resource_type}-{resource_id}.json");
+    rsx! {
+        div {
+            class: "m-4",
+            Link { class: "underline", to: Route::PatientView { id: id.clone(), filter: HistoryFilter::default() }, "Back to patient" }
+            match &*resource.read_unchecked() {
+                Some(Ok(resource)) => {
+                    let json = serde_json::to_string_pretty(resource).unwrap_or_default();
+                    let download = json.clone();
+                    rsx! {
+                        div {
+                            class: "flex items-center gap-3 my-3",
+                            h2 { class: "text-xl font-bold", "{resource_type}/{resource_id}" }
+                            button {
+                                class: "border border-gray-300 rounded px-2",
+                                onclick: move |_| copy_to_clipboard(json.clone()),
+                                "Copy"
+                            }
+                            button {
+                                class: "border border-gray-300 rounded px-2",
+                                onclick: move |_| download_json(file_name.clone(), download.clone()),
+                                "Download"
+                            }
+                        }
+                        JsonTree { value: resource.clone() }
+                        ResourceHistory { id: id.clone(), resource_type: resource_type.clone(), resource_id: resource_id.clone() }
+                    }
+                }
+                Some(Err(e)) => rsx! { ErrorView { title: "Error loading resource", error: e.clone() } },
+                None => rsx! { p { "Loading..." } },
+            }
+        }
+    }
+}
+
+/// The versions of the resource and the differences between two of them.
+#[component]
+fn ResourceHistory(id: String, resource_type: String, resource_id: String) -> Element {
+    let history = use_server_future(use_reactive!(|id, resource_type, resource_id| {
+        server::get_resource_history(id, resource_type, resource_id)
+    }))?;
+    // Positions in the list of versions, which is newest first
+    let mut from = use_signal(|| 1);
+    let mut to = use_signal(|| 0);
+    let history = history.read_unchecked();
+    let versions = match &*history {
+        Some(Ok(versions)) => versions,
+        Some(Err(e)) => {
+            return rsx! { ErrorView { title: "Error loading history", error: e.clone() } }
+        }
+        None => return rsx! { p { class: "my-3", "Loading history..." } },
+    };
+    // Deleted versions are compared as empty resources
+    let resource = |index: usize| {
+        versions
+            .get(index)
+            .and_then(|version| version.resource.clone())
+            .unwrap_or_else(|| serde_json::json!({}))
+    };
+    rsx! {
+        h3 { class: "text-lg font-bold mt-6 mb-3", "History" }
+        div {
+            class: "grid gap-px p-px",
+            style: "grid-template-columns: repeat(5, auto)",
+            div {
+                class: "grid grid-cols-subgrid col-span-full",
+                for header in ["Version", "Last updated", "Change", "From", "To"] {
+                    div { class: "outline outline-gray-300 p-2 bg-gray-100 font-bold", "{header}" }
+                }
+            }
+            for (index, version) in versions.iter().enumerate() {
+                div {
+                    class: "grid grid-cols-subgrid col-span-full",
+                    div { class: "outline outline-gray-300 p-2", "{version.version_id}" }
+                    div {
+                        class: "outline outline-gray-300 p-2",
+                        {version.last_updated.map(fhir::format_time).unwrap_or_default()}
+                    }
+                    div { class: "outline outline-gray-300 p-2", "{version.change}" }
+                    div {
+                        class: "outline outline-gray-300 p-2",
+                        input { r#type: "radio", name: "from", checked: from() == index, onchange: move |_| from.set(index) }
+                    }
+                    div {
+                        class: "outline outline-gray-300 p-2",
+                        input { r#type: "radio", name: "to", checked: to() == index, onchange: move |_| to.set(index) }
+                    }
+                }
+            }
+        }
+        if versions.len() > 1 {
+            h3 { class: "text-lg font-bold mt-6 mb-3", "Changes" }
+            JsonDiff { old: resource(from()), new: resource(to()) }
+        }
+    }
+}
+
+fn copy_to_clipboard(text: String) {
+    let eval = document::eval("navigator.clipboard.writeText(await dioxus.recv());");
+    if let Err(e) = eval.send(text) {
+        tracing::error!("Failed to copy to clipboard: {e}");
+    }
+}
+
+/// Lets the browser save `json` as a file.
+fn download_json(file_name: String, json: String) {
+    let eval = document::eval(
+        r#"
+        const [fileName, json] = await dioxus.recv();
+        const url = URL.createObjectURL(new Blob([json], { type: "application/fhir+json" }));
+        const link = document.createElement("a");
+        link.href = url;
+        link.download = fileName;
+        link.click();
+        URL.revokeObjectURL(url);
+        "#,
+    );
+    if let Err(e) = eval.send((file_name, json)) {
+        tracing::error!("Failed to download resource: {e}");
+    }
+}
@@ -0,0 +1,186 @@
+//! Patient search that is executed by the FHIR server.
+
+use std::fmt;
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The search form of the patient table. It is part of the URL, so searches
+/// can be bookmarked, and is translated into FHIR search parameters of the
+/// Patient resource on the server. Empty fields are ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PatientSearch {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub identifier: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub gender: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub born_from: Option<jiff::civil::Date>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub born_to: Option<jiff::civil::Date>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub city: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_since: Option<jiff::civil::Date>,
+}
+
+#[cfg(feature = "server")]
+impl PatientSearch {
+    /// http://hl7.org/fhir/R4/patient.html#search
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        let mut push = |name, value: &str| {
+            if !value.is_empty() {
+                params.push((name, value.to_string()));
+            }
+        };
+        push("identifier", &self.identifier);
+        push("name", &self.name);
+        push("gender", &self.gender);
+        push("address-city", &self.city);
+        if let Some(date) = self.born_from {
+            params.push(("birthdate", format!("ge{date}")));
+        }
+        if let Some(date) = self.born_to {
+            params.push(("birthdate", format!("le{date}")));
+        }
+        if let Some(date) = self.updated_since {
+            params.push(("_lastUpdated", format!("ge{date}")));
+        }
+        params
+    }
+
+    /// The fields of the Patient resource the search matches on, e.g.
+    /// `Patient.name`, so that users cannot search by fields that are hidden
+    /// from them.
+    pub fn fields(&self) -> Vec<&'static str> {
+        [
+            (!self.identifier.is_empty(), "Patient.identifier"),
+            (!self.name.is_empty(), "Patient.name"),
+            (!self.gender.is_empty(), "Patient.gender"),
+            (!self.city.is_empty(), "Patient.address.city"),
+            (
+                self.born_from.is_some() || self.born_to.is_some(),
+                "Patient.birthDate",
+            ),
+            (self.updated_since.is_some(), "Patient.meta.lastUpdated"),
+        ]
+        .into_iter()
+        .filter_map(|(used, field)| used.then_some(field))
+        .collect()
+    }
+}
+
+impl fmt::Display for PatientSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_urlencoded::to_string(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+impl From<&str> for PatientSearch {
+    fn from(query: &str) -> Self {
+        serde_urlencoded::from_str(query).unwrap_or_default()
+    }
+}
+
+#[component]
+pub fn PatientSearchForm(search: PatientSearch, onsearch: EventHandler<PatientSearch>) -> Element {
+    let mut form = use_signal(|| search.clone());
+    // Follow the URL, e.g. when navigating back to a previous search
+    use_effect(use_reactive!(|search| form.set(search)));
+    let input_class = "border border-gray-300 rounded p-1";
+    rsx! {
+        form {
+            class: "m-4 flex flex-wrap items-end gap-3",
+            onsubmit: move |event| {
+                event.prevent_default();
+                onsearch(form());
+            },
+            label {
+                class: "flex flex-col text-sm",
+                "Identifier"
+                input {
+                    class: input_class,
+                    value: "{form.read().identifier}",
+                    oninput: move |event| form.write().identifier = event.value(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Name"
+                input {
+                    class: input_class,
+                    value: "{form.read().name}",
+                    oninput: move |event| form.write().name = event.value(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Gender"
+                select {
+                    class: input_class,
+                    onchange: move |event| form.write().gender = event.value(),
+                    for (value, text) in [("", "Any"), ("female", "Female"), ("male", "Male"), ("other", "Other"), ("unknown", "Unknown")] {
+                        option { value, selected: form.read().gender == value, "{text}" }
+                    }
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Born from"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().born_from.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().born_from = event.value().parse().ok(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Born until"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().born_to.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().born_to = event.value().parse().ok(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "City"
+                input {
+                    class: input_class,
+                    value: "{form.read().city}",
+                    oninput: move |event| form.write().city = event.value(),
+                }
+            }
+            label {
+                class: "flex flex-col text-sm",
+                "Updated since"
+                input {
+                    class: input_class,
+                    r#type: "date",
+                    value: form.read().updated_since.map(|date| date.to_string()).unwrap_or_default(),
+                    oninput: move |event| form.write().updated_since = event.value().parse().ok(),
+                }
+            }
+            button { class: "border border-gray-300 rounded px-3 py-1", r#type: "submit", "Search" }
+            button {
+                class: "border border-gray-300 rounded px-3 py-1",
+                r#type: "button",
+                onclick: move |_| {
+                    form.set(PatientSearch::default());
+                    onsearch(PatientSearch::default());
+                },
+                "Reset"
+            }
+        }
+    }
+}
@@ -1,119 +1,1177 @@
+use std::fmt;
+use std::str::FromStr;
+
 use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::fhir;
+use crate::history::HistoryFilter;
+use crate::search::PatientSearch;
+
+#[cfg(feature = "server")]
+mod audit;
+#[cfg(feature = "server")]
+mod cache;
+#[cfg(feature = "server")]
+mod client;
+#[cfg(feature = "server")]
+mod oidc;
+#[cfg(feature = "server")]
+mod page_token;
+#[cfg(feature = "server")]
+mod policy;
+#[cfg(feature = "server")]
+mod session;
+#[cfg(feature = "server")]
+mod terminology;
+
+#[cfg(feature = "server")]
+pub use audit::AuditLog;
+#[cfg(feature = "server")]
+pub use cache::ResponseCache;
+#[cfg(feature = "server")]
+pub use client::{FhirClient, RequestError};
+#[cfg(feature = "server")]
+pub use page_token::PageTokens;
+#[cfg(feature = "server")]
+pub use policy::Policy;
+#[cfg(feature = "server")]
+pub use session::Authentication;
+#[cfg(feature = "server")]
+pub use terminology::Terminology;
+
+/// Error of the server functions that return patient data, so that the UI can
+/// tell apart what went wrong and show what the FHIR server had to say.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ServerError {
+    /// Nobody is logged in, the user may not see patient data or the FHIR
+    /// server rejected Scout's credentials
+    Unauthorized(String),
+    /// The patient or resource does not exist or the user may not see it
+    NotFound(String),
+    /// The FHIR server rejected the request, e.g. because it does not support
+    /// a search parameter
+    UpstreamInvalid {
+        status: u16,
+        outcome: Option<fhir::OperationOutcome>,
+    },
+    /// The FHIR server cannot be reached or keeps failing, so it makes sense to
+    /// try again later
+    Unavailable,
+    /// Any other failure to talk to the FHIR server
+    Network(String),
+    /// The FHIR server returned something Scout cannot read
+    Parse(String),
+    /// Anything else, e.g. a failure to write the audit log
+    Internal(String),
+}
+
+impl ServerError {
+    pub fn title(&self) -> &'static str {
+        match self {
+            ServerError::Unauthorized(_) => "Access denied",
+            ServerError::NotFound(_) => "Not found",
+            ServerError::UpstreamInvalid { .. } => "Request rejected by the FHIR server",
+            ServerError::Unavailable => "FHIR server unavailable",
+            ServerError::Network(_) => "FHIR server not reachable",
+            ServerError::Parse(_) => "Invalid response of the FHIR server",
+            ServerError::Internal(_) => "Internal error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ServerError::UpstreamInvalid {
+                status,
+                outcome: Some(outcome),
+            } => format!("The FHIR server responded with {status}: {outcome}"),
+            ServerError::UpstreamInvalid { status, .. } => {
+                format!("The FHIR server responded with {status}")
+            }
+            ServerError::Unavailable => "FHIR server unavailable".to_string(),
+            ServerError::Unauthorized(message)
+            | ServerError::NotFound(message)
+            | ServerError::Network(message)
+            | ServerError::Parse(message)
+            | ServerError::Internal(message) => message.clone(),
+        }
+    }
+}
+
+// server_fn sends custom errors to the browser as their `Display` output and
+// parses them back with `FromStr`, so both use JSON. Use
+// [`ServerError::message`] to show the error to the user.
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+impl FromStr for ServerError {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+/// For server functions that do not return a [`ServerError`].
+impl From<ServerError> for ServerFnError {
+    fn from(e: ServerError) -> Self {
+        ServerFnError::ServerError(e.message())
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<ServerFnError> for ServerError {
+    fn from(e: ServerFnError) -> Self {
+        match e {
+            ServerFnError::ServerError(message) => ServerError::Internal(message),
+            e => ServerError::Internal(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<RequestError> for ServerError {
+    fn from(e: RequestError) -> Self {
+        match e {
+            RequestError::Unavailable => ServerError::Unavailable,
+            RequestError::Status { status, .. }
+                if status == http::StatusCode::NOT_FOUND || status == http::StatusCode::GONE =>
+            {
+                ServerError::NotFound("Resource not found".to_string())
+            }
+            // Scout's own credentials were rejected, even after getting a new token
+            RequestError::Status { status, .. }
+                if status == http::StatusCode::UNAUTHORIZED
+                    || status == http::StatusCode::FORBIDDEN =>
+            {
+                ServerError::Unauthorized("The FHIR server denied access".to_string())
+            }
+            RequestError::Status { status, outcome } => ServerError::UpstreamInvalid {
+                status: status.as_u16(),
+                outcome,
+            },
+            RequestError::Parse(e) => ServerError::Parse(e.to_string()),
+            RequestError::Http(e) if e.is_decode() => ServerError::Parse(e.to_string()),
+            RequestError::Http(e) => ServerError::Network(format!("{:#}", anyhow::Error::from(e))),
+        }
+    }
+}
+
+/// One page of a FHIR search result. `next` and `previous` are opaque page
+/// tokens that can be passed back to the server function that produced the
+/// page to fetch the neighbouring pages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: Option<u32>,
+    pub next: Option<String>,
+    pub previous: Option<String>,
+    /// Whether only the first results are included because there were too
+    /// many to fetch them all
+    pub truncated: bool,
+}
+
+/// One version of a resource from its `_history`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ResourceVersion {
+    pub version_id: String,
+    pub last_updated: Option<jiff::Timestamp>,
+    /// `created`, `updated` or `deleted`
+    pub change: String,
+    /// `None` if the resource was deleted in this version
+    pub resource: Option<serde_json::Value>,
+}
+
+#[cfg(feature = "server")]
+impl ResourceVersion {
+    /// http://hl7.org/fhir/R4/http.html#history
+    fn from_history_entry(entry: &serde_json::Value) -> Self {
+        let resource = entry.get("resource").cloned();
+        let meta = resource.as_ref().map(|resource| &resource["meta"]);
+        // Deleted versions have no resource, so their version is only in the ETag
+        let version_id = meta
+            .and_then(|meta| meta["versionId"].as_str())
+            .or_else(|| {
+                entry["response"]["etag"]
+                    .as_str()
+                    .map(|etag| etag.trim_start_matches("W/").trim_matches('"'))
+            })
+            .unwrap_or_default()
+            .to_string();
+        let last_updated = meta
+            .and_then(|meta| meta["lastUpdated"].as_str())
+            .or_else(|| entry["response"]["lastModified"].as_str())
+            .and_then(|time| time.parse().ok());
+        let change = match entry["request"]["method"].as_str() {
+            Some("POST") => "created",
+            Some("DELETE") => "deleted",
+            _ if resource.is_none() => "deleted",
+            _ => "updated",
+        };
+        Self {
+            version_id,
+            last_updated,
+            change: change.to_string(),
+            resource,
+        }
+    }
+}
+
+/// A user logged into Scout.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub subject: String,
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+impl User {
+    /// The user all requests are attributed to if authentication is disabled.
+    fn anonymous() -> Self {
+        Self {
+            subject: "anonymous".into(),
+            name: "Anonymous".into(),
+            roles: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum AuditAction {
+    ListPatients,
+    ViewPatient,
+    ViewResource,
+}
+
+/// An entry of the audit log recording that a user accessed patient data.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+    pub time: jiff::Timestamp,
+    pub user: String,
+    pub user_name: String,
+    pub action: AuditAction,
+    /// IDs of the patients whose data was returned
+    pub patients: Vec<String>,
+}
+
+/// Filter for browsing the audit log. Empty strings match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuditFilter {
+    pub user: String,
+    pub patient: String,
+    pub from: Option<jiff::civil::Date>,
+    pub to: Option<jiff::civil::Date>,
+}
+
+/// Writes an audit record for an access of `user` to the given patients.
+#[cfg(feature = "server")]
+async fn audit(user: &User, action: AuditAction, patients: Vec<String>) -> Result<(), ServerError> {
+    let log = context::<AuditLog>().await?;
+    log.record(AuditRecord {
+        time: jiff::Timestamp::now(),
+        user: user.subject.clone(),
+        user_name: user.name.clone(),
+        action,
+        patients,
+    })
+    .await
+    .map_err(|e| ServerError::Internal(format!("Failed to write audit log: {e:#}")))
+}
 
+/// Returns a value that was registered with the launch builder.
+#[cfg(feature = "server")]
+async fn context<T>() -> Result<T, ServerError>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let FromContext(value) = extract::<FromContext<T>, _>()
+        .await
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok(value)
+}
+
+/// Returns the shared [`FhirClient`] that was registered with the launch builder.
+#[cfg(feature = "server")]
+async fn fhir_client() -> Result<FhirClient, ServerError> {
+    context().await
+}
+
+/// Fetches the given absolute URL through the response cache of the user and
+/// deserializes the JSON response.
+#[cfg(feature = "server")]
+async fn get_cached<T>(
+    client: &FhirClient,
+    user: &User,
+    url: &str,
+    refresh: bool,
+) -> Result<T, ServerError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let cache = context::<ResponseCache>().await?;
+    Ok(cache.get(client, &user.subject, url, refresh).await?)
+}
+
+#[cfg(feature = "server")]
+async fn terminology() -> Result<Terminology, ServerError> {
+    context().await
+}
+
+#[cfg(feature = "server")]
+async fn page_tokens() -> Result<PageTokens, ServerError> {
+    context().await
+}
+
+#[cfg(feature = "server")]
+async fn authentication() -> Result<Authentication, ServerFnError> {
+    let FromContext(authentication) = extract::<FromContext<Authentication>, _>().await?;
+    Ok(authentication)
+}
+
+/// Returns the user of the current request, or `None` if the request does not
+/// belong to a valid session.
+#[cfg(feature = "server")]
+async fn session_user() -> Result<Option<User>, ServerFnError> {
+    let authentication = authentication().await?;
+    if authentication.provider.is_none() {
+        return Ok(Some(User::anonymous()));
+    }
+    let id = Authentication::session_id(&server_context().request_parts().headers);
+    Ok(id
+        .and_then(|id| authentication.session(&id))
+        .map(|identity| identity.user))
+}
+
+/// Returns the user of the current request and rejects the request with
+/// `401 Unauthorized` if nobody is logged in. Every server function that
+/// returns patient data must call this first.
+#[cfg(feature = "server")]
+async fn require_user() -> Result<User, ServerError> {
+    match session_user().await? {
+        Some(user) => Ok(user),
+        None => {
+            server_context().response_parts_mut().status = http::StatusCode::UNAUTHORIZED;
+            Err(ServerError::Unauthorized("Not logged in".to_string()))
+        }
+    }
+}
+
+/// Returns the policy rule for the user and rejects the request with
+/// `403 Forbidden` if none of the user's roles grants access.
+#[cfg(feature = "server")]
+async fn access_rule(user: &User) -> Result<policy::Rule, ServerError> {
+    let policy = context::<Policy>().await?;
+    match policy.rule(user) {
+        Some(rule) => Ok(rule),
+        None => {
+            server_context().response_parts_mut().status = http::StatusCode::FORBIDDEN;
+            Err(ServerError::Unauthorized(
+                "You are not allowed to access patient data".to_string(),
+            ))
+        }
+    }
+}
+
+#[server]
+pub async fn current_user() -> Result<Option<User>, ServerFnError> {
+    session_user().await
+}
+
+/// Whether the current user's policy rule grants access to the audit log.
+#[server]
+pub async fn can_view_audit_log() -> Result<bool, ServerFnError> {
+    let Some(user) = session_user().await? else {
+        return Ok(false);
+    };
+    let policy = context::<Policy>().await?;
+    Ok(policy.rule(&user).is_some_and(|rule| rule.audit_log))
+}
+
+/// Whether `path` is a path on this site, e.g. `/patient/1`. Browsers read
+/// `//host` and `/\host` as URLs of another host, so the path is resolved
+/// like the browser does and must not change the host.
+#[cfg(feature = "server")]
+fn is_local_path(path: &str) -> bool {
+    let Ok(base) = reqwest::Url::parse("http://scout.invalid/") else {
+        return false;
+    };
+    path.starts_with('/')
+        && base
+            .join(path)
+            .is_ok_and(|url| url.origin() == base.origin())
+}
+
+/// Starts a login and returns the URL of the identity provider the browser
+/// has to be sent to. After the login the user is sent back to `return_to`.
 #[server]
-pub async fn get_patients() -> Result<Vec<fhir::Patient>, ServerFnError> {
-    let url = format!(
-        "{}/Patient",
-        std::env::var("FHIR_BASE_URL").unwrap_or("http://127.0.0.1:8081/fhir".into())
+pub async fn login_url(return_to: String) -> Result<String, ServerFnError> {
+    let authentication = authentication().await?;
+    let provider = authentication
+        .provider
+        .as_ref()
+        .ok_or_else(|| ServerFnError::new("Authentication is disabled"))?;
+    // Only allow local paths to avoid an open redirect
+    let return_to = if is_local_path(&return_to) {
+        return_to
+    } else {
+        "/".to_string()
+    };
+    let (state, nonce) = authentication.begin_login(return_to);
+    let url = provider
+        .authorization_url(&state, &nonce)
+        .await
+        .map_err(|e| ServerFnError::new(format!("{e:#}")))?;
+    server_context().response_parts_mut().headers.append(
+        http::header::SET_COOKIE,
+        authentication.login_cookie(Some(&state)).parse()?,
     );
-    let client = reqwest::Client::new();
-    let bundle = client
-        .get(&url)
-        .basic_auth(
-            std::env::var("FHIR_USERNAME").unwrap_or_default(),
-            Some(std::env::var("FHIR_PASSWORD").unwrap_or_default()),
+    Ok(url)
+}
+
+/// Completes a login with the parameters the identity provider passed to the
+/// callback, sets the session cookie and returns the path to continue at.
+#[server]
+pub async fn complete_login(code: String, state: String) -> Result<String, ServerFnError> {
+    let authentication = authentication().await?;
+    let provider = authentication
+        .provider
+        .as_ref()
+        .ok_or_else(|| ServerFnError::new("Authentication is disabled"))?;
+    let cookie = Authentication::login_state(&server_context().request_parts().headers);
+    let login = authentication
+        .finish_login(&state, cookie.as_deref())
+        .ok_or_else(|| ServerFnError::new("Login expired, please try again"))?;
+    server_context().response_parts_mut().headers.append(
+        http::header::SET_COOKIE,
+        authentication.login_cookie(None).parse()?,
+    );
+    let identity = provider
+        .exchange(&code, &login.nonce)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Login failed: {e:#}")))?;
+    tracing::info!("User {} logged in", identity.user.subject);
+    let id = authentication.create_session(identity);
+    server_context().response_parts_mut().headers.append(
+        http::header::SET_COOKIE,
+        authentication.cookie(Some(&id)).parse()?,
+    );
+    Ok(login.return_to)
+}
+
+/// Ends the current session and returns the URL to end the session at the
+/// identity provider as well, if it supports that.
+#[server]
+pub async fn logout() -> Result<Option<String>, ServerFnError> {
+    let authentication = authentication().await?;
+    let Some(ref provider) = authentication.provider else {
+        return Ok(None);
+    };
+    let identity = Authentication::session_id(&server_context().request_parts().headers)
+        .and_then(|id| authentication.remove_session(&id));
+    server_context().response_parts_mut().headers.insert(
+        http::header::SET_COOKIE,
+        authentication.cookie(None).parse()?,
+    );
+    match identity {
+        Some(identity) => {
+            tracing::info!("User {} logged out", identity.user.subject);
+            Ok(provider.end_session_url(identity.id_token.as_deref()).await)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns the newest audit records matching the filter. Only users whose
+/// policy rule allows it may browse the audit log.
+#[server]
+pub async fn get_audit_log(
+    filter: AuditFilter,
+) -> Result<Vec<AuditRecord>, ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    if !access_rule(&user).await?.audit_log {
+        server_context().response_parts_mut().status = http::StatusCode::FORBIDDEN;
+        return Err(ServerError::Unauthorized(
+            "You are not allowed to view the audit log".to_string(),
         )
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<fhir::FhirBundle<fhir::Patient>>()
-        .await?;
-    Ok(bundle
-        .entry
-        .into_iter()
-        .map(|entry| entry.resource)
-        .collect())
+        .into());
+    }
+    let log = context::<AuditLog>().await?;
+    Ok(log
+        .search(filter, 500)
+        .await
+        .map_err(|e| ServerError::Internal(format!("Failed to read audit log: {e:#}")))?)
 }
 
-pub async fn get_resources<T>(resource_type: &str) -> Result<Vec<T>, ServerFnError>
+/// Fetches a single page of a search. If `page` is `None` the first page of
+/// `resource_type` is requested with the search parameters `params` and
+/// `count` entries per page, otherwise the page identified by the token is
+/// fetched. The response is cached for the user unless `refresh` is set.
+#[cfg(feature = "server")]
+pub async fn get_resource_page<T>(
+    client: &FhirClient,
+    user: &User,
+    refresh: bool,
+    resource_type: &str,
+    params: &[(&str, String)],
+    page: Option<String>,
+    count: u32,
+) -> Result<Page<T>, ServerError>
 where
     T: serde::de::DeserializeOwned,
 {
-    let url = format!(
-        "{}/{}",
-        std::env::var("FHIR_BASE_URL").unwrap_or("http://127.0.0.1:8081/fhir".into()),
-        resource_type
+    let url = match page {
+        Some(token) => client
+            .page_url(&token)
+            .ok_or_else(|| ServerError::NotFound("Invalid page token".to_string()))?,
+        None => {
+            let count = count.to_string();
+            let params = params
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .chain([("_count", count.as_str()), ("_total", "accurate")]);
+            reqwest::Url::parse_with_params(&client.url(resource_type), params)
+                .map_err(|e| ServerError::Internal(e.to_string()))?
+                .into()
+        }
+    };
+    let bundle = get_cached::<fhir::FhirBundle<T>>(client, user, &url, refresh).await?;
+    Ok(Page {
+        total: bundle.total,
+        next: bundle.link("next").and_then(|link| client.page_token(link)),
+        previous: bundle
+            .link("previous")
+            .and_then(|link| client.page_token(link)),
+        items: bundle
+            .entry
+            .into_iter()
+            .map(|entry| entry.resource)
+            .collect(),
+        truncated: false,
+    })
+}
+
+/// Upper bound for the pages [`get_resources`] and [`get_resource_history`]
+/// follow, so that a FHIR server that keeps returning `next` links cannot keep
+/// a request busy forever.
+#[cfg(feature = "server")]
+const MAX_PAGES: usize = 100;
+
+/// Page size [`get_resources`] asks for, so that few pages are needed. FHIR
+/// servers may return smaller pages.
+#[cfg(feature = "server")]
+const ALL_PAGE_SIZE: u32 = 1000;
+
+/// Fetches all resources of a type matching the search parameters `params` by
+/// following the `next` links of the searchset until the last page is reached,
+/// and returns them as a single page. After [`MAX_PAGES`] pages the page is
+/// marked as truncated, a repeating `next` link is an error. The responses are
+/// cached for the user unless `refresh` is set.
+#[cfg(feature = "server")]
+pub async fn get_resources<T>(
+    client: &FhirClient,
+    user: &User,
+    refresh: bool,
+    resource_type: &str,
+    params: &[(&str, String)],
+) -> Result<Page<T>, ServerError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let count = ALL_PAGE_SIZE.to_string();
+    let params = params
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain([("_count", count.as_str()), ("_total", "accurate")]);
+    let mut url: String = reqwest::Url::parse_with_params(&client.url(resource_type), params)
+        .map_err(|e| ServerError::Internal(e.to_string()))?
+        .into();
+    let mut page = Page {
+        items: Vec::new(),
+        total: None,
+        next: None,
+        previous: None,
+        truncated: true,
+    };
+    let mut visited = std::collections::HashSet::new();
+    for _ in 0..MAX_PAGES {
+        let bundle = get_cached::<fhir::FhirBundle<T>>(client, user, &url, refresh).await?;
+        let next = bundle.link("next").and_then(|link| client.page_token(link));
+        page.total = page.total.or(bundle.total);
+        page.items
+            .extend(bundle.entry.into_iter().map(|entry| entry.resource));
+        visited.insert(url);
+        match next.and_then(|token| client.page_url(&token)) {
+            Some(next) => url = next,
+            None => {
+                page.truncated = false;
+                return Ok(page);
+            }
+        }
+        if visited.contains(&url) {
+            return Err(ServerError::Parse(
+                "FHIR server returned a next link twice".into(),
+            ));
+        }
+    }
+    tracing::info!("Stopped following next links after {MAX_PAGES} pages");
+    Ok(page)
+}
+
+/// Searches the patients the user may see. If `count` is `None`, all matches
+/// are returned in a single page. With `refresh` cached responses are not
+/// used.
+#[server]
+pub async fn search_patients(
+    search: PatientSearch,
+    page: Option<String>,
+    count: Option<u32>,
+    refresh: bool,
+) -> Result<Page<fhir::Patient>, ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    let rule = access_rule(&user).await?;
+    if let Some(field) = search.fields().into_iter().find(|field| rule.hides(field)) {
+        server_context().response_parts_mut().status = http::StatusCode::FORBIDDEN;
+        return Err(
+            ServerError::Unauthorized(format!("You are not allowed to search by {field}")).into(),
+        );
+    }
+    let client = fhir_client().await?;
+    let mut params = search.params();
+    params.extend(rule.patient_search_params());
+    // Page tokens only work for the user and search they were created for, so
+    // that they cannot be used to search differently
+    let tokens = page_tokens().await?;
+    let scope = format!(
+        "{}\n{}\n{count:?}",
+        user.subject,
+        serde_urlencoded::to_string(&params).map_err(|e| ServerError::Internal(e.to_string()))?
     );
-    let client = reqwest::Client::new();
-    let bundle = client
-        .get(&url)
-        .basic_auth(
-            std::env::var("FHIR_USERNAME").unwrap_or_default(),
-            Some(std::env::var("FHIR_PASSWORD").unwrap_or_default()),
-        )
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<fhir::FhirBundle<T>>()
-        .await?;
-    Ok(bundle
-        .entry
+    let page = page
+        .map(|page| {
+            tokens
+                .verify(&scope, &page)
+                .ok_or_else(|| ServerError::NotFound("Invalid page token".to_string()))
+        })
+        .transpose()?;
+    let page = match count {
+        Some(count) => {
+            get_resource_page::<serde_json::Value>(
+                &client, &user, refresh, "Patient", &params, page, count,
+            )
+            .await?
+        }
+        None => get_resources(&client, &user, refresh, "Patient", &params).await?,
+    };
+    let fetched = page.items.len();
+    let items = page
+        .items
         .into_iter()
-        .map(|entry| entry.resource)
-        .collect())
+        .filter(|patient| rule.allows_patient(patient))
+        .map(|mut patient| {
+            rule.strip(&mut patient);
+            serde_json::from_value::<fhir::Patient>(patient)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ServerError::Parse(e.to_string()))?;
+    // The FHIR server also counted the patients that were left out
+    let total = page.total.filter(|_| items.len() == fetched);
+    audit(
+        &user,
+        AuditAction::ListPatients,
+        items.iter().map(|patient| patient.id()).collect(),
+    )
+    .await?;
+    Ok(Page {
+        items,
+        total,
+        next: page.next.map(|token| tokens.sign(&scope, &token)),
+        previous: page.previous.map(|token| tokens.sign(&scope, &token)),
+        truncated: page.truncated,
+    })
 }
 
-pub async fn get_resource<T>(resource_type: &str, id: &str) -> anyhow::Result<T>
+#[cfg(feature = "server")]
+pub async fn get_resource<T>(
+    client: &FhirClient,
+    resource_type: &str,
+    id: &str,
+) -> Result<T, RequestError>
 where
     T: serde::de::DeserializeOwned,
 {
-    let url = format!(
-        "{}/{}/{}",
-        std::env::var("FHIR_BASE_URL").unwrap_or("http://127.0.0.1:8081/fhir".into()),
-        resource_type,
-        id
-    );
-    let client = reqwest::Client::new();
-    let resource = client
-        .get(&url)
-        .basic_auth(
-            std::env::var("FHIR_USERNAME").unwrap_or_default(),
-            Some(std::env::var("FHIR_PASSWORD").unwrap_or_default()),
-        )
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<T>()
-        .await?;
+    client
+        .get::<T>(&client.url(&format!("{resource_type}/{id}")))
+        .await
+}
+
+/// Whether `id` is a valid FHIR resource ID, so it can be used as a URL path
+/// segment.
+#[cfg(feature = "server")]
+fn is_valid_id(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// Whether `resource_type` looks like a FHIR resource type, e.g.
+/// `Observation`, so it can be used as a URL path segment. Resource types
+/// start with an upper case letter, which rules out `_history` or `metadata`.
+#[cfg(feature = "server")]
+fn is_resource_type(resource_type: &str) -> bool {
+    resource_type
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_uppercase())
+        && resource_type.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// Checks that the user may see the resource `resource_type/resource_id` of a
+/// patient as far as possible without fetching the resource, and returns the
+/// patient.
+#[cfg(feature = "server")]
+async fn check_patient_resource(
+    client: &FhirClient,
+    rule: &policy::Rule,
+    patient_id: &str,
+    resource_type: &str,
+    resource_id: &str,
+) -> Result<serde_json::Value, ServerError> {
+    if !is_valid_id(patient_id)
+        || !is_valid_id(resource_id)
+        || !is_resource_type(resource_type)
+        || !rule.allows_resource_type(resource_type)
+    {
+        return Err(ServerError::NotFound("Resource not found".to_string()));
+    }
+    let patient = get_resource::<serde_json::Value>(client, "Patient", patient_id).await?;
+    if !rule.allows_patient(&patient) {
+        return Err(ServerError::NotFound("Resource not found".to_string()));
+    }
+    Ok(patient)
+}
+
+/// Whether a resource is the patient itself or references the patient.
+#[cfg(feature = "server")]
+fn belongs_to_patient(resource: &serde_json::Value, patient_id: &str) -> bool {
+    if resource["resourceType"] == "Patient" {
+        resource["id"] == patient_id
+    } else {
+        crate::json::references(resource, &format!("Patient/{patient_id}"))
+    }
+}
+
+/// Get a single resource of a patient as JSON, restricted to what the user may
+/// see. Only the patient itself and resources that reference the patient can
+/// be fetched.
+#[server]
+pub async fn get_patient_resource(
+    patient_id: String,
+    resource_type: String,
+    resource_id: String,
+) -> Result<serde_json::Value, ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    let rule = access_rule(&user).await?;
+    let client = fhir_client().await?;
+    let patient =
+        check_patient_resource(&client, &rule, &patient_id, &resource_type, &resource_id).await?;
+    let mut resource = if resource_type == "Patient" && resource_id == patient_id {
+        patient
+    } else {
+        get_resource::<serde_json::Value>(&client, &resource_type, &resource_id)
+            .await
+            .map_err(ServerError::from)?
+    };
+    if !belongs_to_patient(&resource, &patient_id) {
+        return Err(ServerError::NotFound("Resource not found".to_string()).into());
+    }
+    rule.strip(&mut resource);
+    audit(&user, AuditAction::ViewResource, vec![patient_id]).await?;
     Ok(resource)
 }
 
-/// Get a patient and their related resources. For now returns the patient and their encounters.
+/// Get all versions of a resource of a patient, newest first. The newest
+/// version that was not deleted must belong to the patient, older versions
+/// that belonged to another patient are left out. At most [`MAX_PAGES`] pages
+/// of the history are fetched.
+#[server]
+pub async fn get_resource_history(
+    patient_id: String,
+    resource_type: String,
+    resource_id: String,
+) -> Result<Vec<ResourceVersion>, ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    let rule = access_rule(&user).await?;
+    let client = fhir_client().await?;
+    check_patient_resource(&client, &rule, &patient_id, &resource_type, &resource_id).await?;
+
+    let mut versions = Vec::new();
+    let mut url = client.url(&format!("{resource_type}/{resource_id}/_history"));
+    let mut visited = std::collections::HashSet::new();
+    for page in 1.. {
+        let bundle = get_cached::<serde_json::Value>(&client, &user, &url, false).await?;
+        let next = bundle["link"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|link| link["relation"] == "next")
+            .and_then(|link| client.page_token(link["url"].as_str()?));
+        for entry in bundle["entry"].as_array().into_iter().flatten() {
+            versions.push(ResourceVersion::from_history_entry(entry));
+        }
+        visited.insert(url);
+        match next.and_then(|token| client.page_url(&token)) {
+            Some(_) if page == MAX_PAGES => {
+                tracing::warn!("Stopped following next links after {MAX_PAGES} pages");
+                break;
+            }
+            Some(next) if !visited.contains(&next) => url = next,
+            Some(_) => {
+                tracing::warn!("FHIR server returned a next link that was already visited");
+                break;
+            }
+            None => break,
+        }
+    }
+
+    // The first version was created, even if with a PUT
+    if let Some(oldest) = versions
+        .last_mut()
+        .filter(|oldest| oldest.change == "updated")
+    {
+        oldest.change = "created".to_string();
+    }
+
+    let current = versions
+        .iter()
+        .find_map(|version| version.resource.as_ref());
+    if !current.is_some_and(|resource| belongs_to_patient(resource, &patient_id)) {
+        return Err(ServerError::NotFound("Resource not found".to_string()).into());
+    }
+    // Earlier versions may have belonged to another patient, e.g. if the
+    // subject was corrected
+    versions.retain(|version| {
+        version
+            .resource
+            .as_ref()
+            .is_none_or(|resource| belongs_to_patient(resource, &patient_id))
+    });
+    for resource in versions
+        .iter_mut()
+        .filter_map(|version| version.resource.as_mut())
+    {
+        rule.strip(resource);
+    }
+    audit(&user, AuditAction::ViewResource, vec![patient_id]).await?;
+    Ok(versions)
+}
+
+/// Number of resources per page of `Patient/$everything`.
+#[cfg(feature = "server")]
+const EVERYTHING_PAGE_SIZE: u32 = 500;
+
+/// Resource types that `$everything` returns because the patient's resources
+/// refer to them, although they do not refer to the patient themselves.
+#[cfg(feature = "server")]
+const SHARED_RESOURCE_TYPES: [&str; 6] = [
+    "Medication",
+    "Organization",
+    "Location",
+    "Practitioner",
+    "PractitionerRole",
+    "Substance",
+];
+
+/// Fetches a page of `Patient/$everything` through the response cache of the
+/// user and returns its resources and the token of the next page.
+#[cfg(feature = "server")]
+async fn get_everything_page(
+    client: &FhirClient,
+    user: &User,
+    url: &str,
+    refresh: bool,
+) -> Result<(Vec<serde_json::Value>, Option<String>), ServerError> {
+    let bundle =
+        get_cached::<fhir::FhirBundle<serde_json::Value>>(client, user, url, refresh).await?;
+    let next = bundle.link("next").and_then(|link| client.page_token(link));
+    let resources = bundle
+        .entry
+        .into_iter()
+        .map(|entry| entry.resource)
+        .collect();
+    Ok((resources, next))
+}
+
+/// The scope of `$everything` page tokens. They only work for the user,
+/// patient and filter they were created for, so that they cannot be used to
+/// page through the resources of another patient.
+#[cfg(feature = "server")]
+fn everything_scope(user: &User, id: &str, filter: &HistoryFilter) -> Result<String, ServerError> {
+    let params = serde_urlencoded::to_string(filter.params())
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok(format!("{}\n{id}\n{params}", user.subject))
+}
+
+/// Keeps the resources of `$everything` that belong to the patient or are
+/// shared between patients, so that a misbehaving FHIR server or a forged
+/// page token cannot leak the data of other patients.
+#[cfg(feature = "server")]
+fn retain_patient_resources(resources: &mut Vec<serde_json::Value>, patient_id: &str) {
+    resources.retain(|resource| {
+        belongs_to_patient(resource, patient_id)
+            || resource["resourceType"]
+                .as_str()
+                .is_some_and(|resource_type| SHARED_RESOURCE_TYPES.contains(&resource_type))
+    });
+}
+
+/// Removes the resource types and fields the user may not see, adds missing
+/// code displays and parses the resources that match the filter.
+#[cfg(feature = "server")]
+async fn prepare_resources(
+    rule: &policy::Rule,
+    filter: &HistoryFilter,
+    mut resources: Vec<serde_json::Value>,
+) -> Result<Vec<fhir::MixedEntry>, ServerError> {
+    resources.retain(|resource| {
+        resource["resourceType"]
+            .as_str()
+            .is_some_and(|resource_type| rule.allows_resource_type(resource_type))
+    });
+    for resource in resources.iter_mut() {
+        rule.strip(resource);
+    }
+    resources.retain(|resource| filter.changed(resource));
+    terminology().await?.fill_displays(&mut resources).await;
+    Ok(resources
+        .into_iter()
+        .map(fhir::MixedEntry::from_resource)
+        .filter(|entry| filter.includes(entry))
+        .collect())
+}
+
+/// Get a patient and the first page of their related resources matching the
+/// filter, restricted to what the user may see. The following pages can be
+/// fetched with [`get_patient_history_page`]. With `refresh` cached responses
+/// are not used. The patient is `None` if it does not match the model; it is
+/// then part of the page as [`fhir::Resource::Invalid`].
 #[server]
 pub async fn get_patient_details(
     id: String,
-) -> Result<(fhir::Patient, fhir::MixedBundle), ServerFnError> {
-    let url = format!(
-        "{}/Patient/{}/$everything",
-        std::env::var("FHIR_BASE_URL").unwrap_or("http://127.0.0.1:8081/fhir".into()),
-        id
-    );
-    let client = reqwest::Client::new();
-    let mut bundle = client
-        .get(&url)
-        .basic_auth(
-            std::env::var("FHIR_USERNAME").unwrap_or_default(),
-            Some(std::env::var("FHIR_PASSWORD").unwrap_or_default()),
-        )
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<fhir::MixedBundle>()
-        .await?;
+    filter: HistoryFilter,
+    refresh: bool,
+) -> Result<(Option<fhir::Patient>, Page<fhir::MixedEntry>), ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    let rule = access_rule(&user).await?;
+    if !is_valid_id(&id) {
+        return Err(ServerError::NotFound("No patient found".to_string()).into());
+    }
+    let client = fhir_client().await?;
+    let url = reqwest::Url::parse_with_params(
+        &client.url(&format!("Patient/{id}/$everything")),
+        filter
+            .params()
+            .into_iter()
+            .chain([("_count", EVERYTHING_PAGE_SIZE.to_string())]),
+    )
+    .map_err(|e| ServerError::Internal(e.to_string()))?;
+    let (mut resources, next) = get_everything_page(&client, &user, url.as_str(), refresh).await?;
+    retain_patient_resources(&mut resources, &id);
+    // `_since` also applies to the patient, who is needed nonetheless
+    if filter.changed_since.is_some()
+        && !resources
+            .iter()
+            .any(|resource| resource["resourceType"] == "Patient")
+    {
+        let patient = get_resource(&client, "Patient", &id)
+            .await
+            .map_err(ServerError::from)?;
+        resources.insert(0, patient);
+    }
 
-    let patient = bundle
-        .entry
-        .iter()
-        .find_map(|entry| {
-            if let fhir::Resource::Patient(patient) = &entry.resource {
-                Some(patient.clone())
-            } else {
-                None
-            }
-        })
-        .ok_or_else(|| ServerFnError::new("No patient found"))?;
+    // Other patients were removed above, duplicates of the patient must all be allowed
+    let allowed = {
+        let mut patients = resources
+            .iter()
+            .filter(|resource| resource["resourceType"] == "Patient")
+            .peekable();
+        patients.peek().is_some() && patients.all(|patient| rule.allows_patient(patient))
+    };
+    if !allowed {
+        return Err(ServerError::NotFound("No patient found".to_string()).into());
+    }
+    let items = prepare_resources(&rule, &filter, resources).await?;
+
+    let patient = items.iter().find_map(|entry| match entry.resource {
+        fhir::Resource::Patient(ref patient) => Some(patient.clone()),
+        _ => None,
+    });
+    let scope = everything_scope(&user, &id, &filter)?;
+    let tokens = page_tokens().await?;
+    let next = next.map(|token| tokens.sign(&scope, &token));
+    audit(&user, AuditAction::ViewPatient, vec![id]).await?;
+
+    Ok((
+        patient,
+        Page {
+            items,
+            total: None,
+            next,
+            previous: None,
+            truncated: false,
+        },
+    ))
+}
+
+/// Get a following page of the resources of a patient, identified by the
+/// `next` token of the previous page. The filter must be the one the first
+/// page was fetched with, tokens of other users, patients or filters are
+/// rejected. With `refresh` cached responses are not used.
+#[server]
+pub async fn get_patient_history_page(
+    id: String,
+    filter: HistoryFilter,
+    page: String,
+    refresh: bool,
+) -> Result<Page<fhir::MixedEntry>, ServerFnError<ServerError>> {
+    let user = require_user().await?;
+    let rule = access_rule(&user).await?;
+    let client = fhir_client().await?;
+    check_patient_resource(&client, &rule, &id, "Patient", &id).await?;
+    let scope = everything_scope(&user, &id, &filter)?;
+    let tokens = page_tokens().await?;
+    let url = tokens
+        .verify(&scope, &page)
+        .and_then(|page| client.page_url(&page))
+        .ok_or_else(|| ServerError::NotFound("Invalid page token".to_string()))?;
+    let (mut resources, next) = get_everything_page(&client, &user, &url, refresh).await?;
+    // Kept as a second line of defence should the FHIR server link elsewhere
+    retain_patient_resources(&mut resources, &id);
+    let items = prepare_resources(&rule, &filter, resources).await?;
+    audit(&user, AuditAction::ViewPatient, vec![id]).await?;
+
+    Ok(Page {
+        items,
+        total: None,
+        next: next.map(|token| tokens.sign(&scope, &token)),
+        previous: None,
+        truncated: false,
+    })
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn maps_upstream_errors() {
+        let status = |status| {
+            ServerError::from(RequestError::Status {
+                status,
+                outcome: None,
+            })
+        };
+        assert!(matches!(
+            status(http::StatusCode::UNAUTHORIZED),
+            ServerError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            status(http::StatusCode::FORBIDDEN),
+            ServerError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            status(http::StatusCode::GONE),
+            ServerError::NotFound(_)
+        ));
+        assert!(matches!(
+            status(http::StatusCode::UNPROCESSABLE_ENTITY),
+            ServerError::UpstreamInvalid { status: 422, .. }
+        ));
+        assert!(matches!(
+            ServerError::from(RequestError::Unavailable),
+            ServerError::Unavailable
+        ));
+    }
+
+    #[test]
+    fn return_to_must_be_local() {
+        for path in ["/", "/patient/1?type=Observation", "/admin/audit"] {
+            assert!(is_local_path(path), "{path}");
+        }
+        for path in [
+            "",
+            "patient/1",
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "https://evil.example",
+        ] {
+            assert!(!is_local_path(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn checks_resource_types() {
+        assert!(is_resource_type("Observation"));
+        assert!(is_resource_type("MedicationRequest"));
+        for invalid in ["", "observation", "_history", "$everything", "Patient/1"] {
+            assert!(!is_resource_type(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn resources_belong_to_their_patient_only() {
+        assert!(belongs_to_patient(
+            &json!({"resourceType": "Patient", "id": "1"}),
+            "1"
+        ));
+        assert!(!belongs_to_patient(
+            &json!({"resourceType": "Patient", "id": "2"}),
+            "1"
+        ));
+        let observation = json!({
+            "resourceType": "Observation",
+            "subject": {"reference": "Patient/1"},
+            "performer": [{"reference": "Practitioner/2"}]
+        });
+        assert!(belongs_to_patient(&observation, "1"));
+        assert!(!belongs_to_patient(&observation, "2"));
+        assert!(!belongs_to_patient(&observation, "12"));
+    }
+
+    #[test]
+    fn everything_page_tokens_are_scoped_to_user_patient_and_filter() {
+        let user = |subject: &str| User {
+            subject: subject.into(),
+            name: subject.into(),
+            roles: Vec::new(),
+        };
+        let filter = HistoryFilter {
+            types: "Condition".into(),
+            ..Default::default()
+        };
+        let tokens = PageTokens::default();
+        let scope = everything_scope(&user("alice"), "1", &filter).unwrap();
+        let signed = tokens.sign(&scope, "page-2");
+        assert_eq!(tokens.verify(&scope, &signed).as_deref(), Some("page-2"));
 
-    Ok((patient, bundle))
+        let tampered = format!("{}3", signed.strip_suffix('2').unwrap());
+        assert_eq!(tokens.verify(&scope, &tampered), None);
+        for other in [
+            everything_scope(&user("bob"), "1", &filter),
+            everything_scope(&user("alice"), "2", &filter),
+            everything_scope(&user("alice"), "1", &HistoryFilter::default()),
+        ] {
+            assert_eq!(tokens.verify(&other.unwrap(), &signed), None);
+        }
+    }
 }
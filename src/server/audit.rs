@@ -0,0 +1,178 @@
+//! Audit trail of patient data accesses.
+//!
+//! Every access is appended as a JSON line to the file at `AUDIT_LOG`
+//! (defaults to `audit.log`). If `AUDIT_FHIR_BASE_URL` is set, each access is
+//! additionally sent as an `AuditEvent` to that FHIR server, which is
+//! configured like the main FHIR client with the `AUDIT_FHIR_` prefix.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use serde_json::json;
+
+use super::{AuditAction, AuditFilter, AuditRecord, FhirClient};
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+    fhir: Option<FhirClient>,
+}
+
+impl AuditLog {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let path = PathBuf::from(std::env::var("AUDIT_LOG").unwrap_or("audit.log".into()));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        let fhir = match std::env::var("AUDIT_FHIR_BASE_URL") {
+            Ok(_) => Some(FhirClient::from_env_with_prefix("AUDIT_FHIR")?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+            fhir,
+        })
+    }
+
+    /// Records an access. Writing to the log file must succeed, otherwise the
+    /// data must not be returned to the user. Sending the `AuditEvent` happens
+    /// in the background and failures are only logged.
+    pub async fn record(&self, record: AuditRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let mut file = file.lock().unwrap();
+            file.write_all(line.as_bytes())?;
+            file.flush()
+        })
+        .await??;
+        if let Some(ref fhir) = self.fhir {
+            let fhir = fhir.clone();
+            let event = audit_event(&record);
+            tokio::spawn(async move {
+                if let Err(e) = fhir.create("AuditEvent", &event).await {
+                    tracing::error!("Failed to send AuditEvent: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the records matching the filter, newest first. Lines that
+    /// cannot be read, e.g. because a write was cut off, are skipped.
+    pub async fn search(
+        &self,
+        filter: AuditFilter,
+        limit: usize,
+    ) -> anyhow::Result<Vec<AuditRecord>> {
+        let path = self.path.clone();
+        let records = tokio::task::spawn_blocking(move || read_records(&path, &filter)).await??;
+        Ok(records.into_iter().rev().take(limit).collect())
+    }
+}
+
+fn read_records(path: &Path, filter: &AuditFilter) -> anyhow::Result<Vec<AuditRecord>> {
+    let file = File::open(path)?;
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let record = line
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(serde_json::from_str::<AuditRecord>(&line)?));
+        match record {
+            Ok(record) if matches(filter, &record) => records.push(record),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(
+                "Skipping invalid line {} of audit log {}: {e}",
+                number + 1,
+                path.display()
+            ),
+        }
+    }
+    Ok(records)
+}
+
+fn matches(filter: &AuditFilter, record: &AuditRecord) -> bool {
+    let date = record.time.to_zoned(jiff::tz::TimeZone::UTC).date();
+    let user = filter.user.to_lowercase();
+    (user.is_empty()
+        || record.user.to_lowercase().contains(&user)
+        || record.user_name.to_lowercase().contains(&user))
+        && (filter.patient.is_empty() || record.patients.contains(&filter.patient))
+        && filter.from.is_none_or(|from| date >= from)
+        && filter.to.is_none_or(|to| date <= to)
+}
+
+/// http://hl7.org/fhir/R4/auditevent.html
+fn audit_event(record: &AuditRecord) -> serde_json::Value {
+    let (subtype, action) = match record.action {
+        AuditAction::ListPatients => ("search-type", "E"),
+        AuditAction::ViewPatient | AuditAction::ViewResource => ("read", "R"),
+    };
+    json!({
+        "resourceType": "AuditEvent",
+        "type": {
+            "system": "http://terminology.hl7.org/CodeSystem/audit-event-type",
+            "code": "rest",
+            "display": "RESTful Operation"
+        },
+        "subtype": [{
+            "system": "http://hl7.org/fhir/restful-interaction",
+            "code": subtype
+        }],
+        "action": action,
+        "recorded": record.time.to_string(),
+        "outcome": "0",
+        "agent": [{
+            "who": {
+                "identifier": { "value": record.user },
+                "display": record.user_name
+            },
+            "requestor": true
+        }],
+        "source": {
+            "observer": { "display": env!("CARGO_PKG_NAME") }
+        },
+        "entity": record.patients.iter().map(|id| json!({
+            "what": { "reference": format!("Patient/{id}") }
+        })).collect::<Vec<_>>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn search_skips_invalid_lines() {
+        let path = std::env::temp_dir().join(format!("scout-audit-{}.log", std::process::id()));
+        let log = AuditLog {
+            path: path.clone(),
+            file: Arc::new(Mutex::new(File::create(&path).unwrap())),
+            fhir: None,
+        };
+        let record = |user: &str| AuditRecord {
+            time: jiff::Timestamp::now(),
+            user: user.into(),
+            user_name: user.into(),
+            action: AuditAction::ViewPatient,
+            patients: vec!["1".into()],
+        };
+        log.record(record("alice")).await.unwrap();
+        // A write that was cut off
+        log.file.lock().unwrap().write_all(b"{\"time\":\n").unwrap();
+        log.record(record("bob")).await.unwrap();
+
+        let records = log.search(AuditFilter::default(), 10).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let users = records.iter().map(|r| r.user.as_str()).collect::<Vec<_>>();
+        assert_eq!(users, ["bob", "alice"]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Cache of FHIR server responses.
+//!
+//! Responses are cached per user and URL. For `CACHE_TTL` seconds (defaults
+//! to 60), or the `max-age` of their `Cache-Control` header, they are served
+//! without asking the FHIR server. After that, and always for `no-cache`
+//! responses, they are revalidated with `If-None-Match` and
+//! `If-Modified-Since`, so that unchanged responses are not transferred again.
+//! `no-store` responses are not cached. The cache holds at most `CACHE_SIZE`
+//! megabytes (defaults to 64) of response bodies and drops the least recently
+//! used ones first. `CACHE_SIZE=0` disables the cache.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use lru::LruCache;
+use serde::de::DeserializeOwned;
+
+use super::client::ValidatedResponse;
+use super::{FhirClient, RequestError};
+
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    /// In bytes
+    max_size: usize,
+    entries: Arc<Mutex<Entries>>,
+}
+
+/// User and URL
+type Key = (String, String);
+
+#[derive(Debug)]
+struct Entries {
+    lru: LruCache<Key, Entry>,
+    /// Of the response bodies in bytes
+    size: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: ValidatedResponse,
+    /// When the response was last received or revalidated
+    validated: Instant,
+}
+
+impl ResponseCache {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let ttl = match std::env::var("CACHE_TTL") {
+            Ok(ttl) => ttl
+                .parse()
+                .context("CACHE_TTL must be a number of seconds")?,
+            Err(_) => 60,
+        };
+        let max_size: usize = match std::env::var("CACHE_SIZE") {
+            Ok(size) => size
+                .parse()
+                .context("CACHE_SIZE must be a number of megabytes")?,
+            Err(_) => 64,
+        };
+        Ok(Self::new(Duration::from_secs(ttl), max_size * 1024 * 1024))
+    }
+
+    fn new(ttl: Duration, max_size: usize) -> Self {
+        Self {
+            ttl,
+            max_size,
+            entries: Arc::new(Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                size: 0,
+            })),
+        }
+    }
+
+    /// Fetches the given absolute URL for a user and deserializes the JSON
+    /// response. With `refresh` the cached response is not used, e.g. because
+    /// the user explicitly asked for the current data.
+    pub async fn get<T>(
+        &self,
+        client: &FhirClient,
+        user: &str,
+        url: &str,
+        refresh: bool,
+    ) -> Result<T, RequestError>
+    where
+        T: DeserializeOwned,
+    {
+        let key = (user.to_string(), url.to_string());
+        let cached = if refresh || self.max_size == 0 {
+            None
+        } else {
+            self.entries.lock().unwrap().lru.get(&key).map(|entry| {
+                let max_age = entry.response.max_age.unwrap_or(self.ttl);
+                (entry.validated.elapsed() < max_age, entry.response.clone())
+            })
+        };
+        let response = match cached {
+            Some((true, response)) => response,
+            Some((false, response)) => {
+                let etag = response.etag.as_deref();
+                let last_modified = response.last_modified.as_deref();
+                match client.get_if_changed(url, etag, last_modified).await? {
+                    Some(changed) => self.store(key, changed),
+                    None => self.store(key, response),
+                }
+            }
+            None => {
+                let Some(response) = client.get_if_changed(url, None, None).await? else {
+                    // A 304 to an unconditional request means the server is broken
+                    return Err(RequestError::Status {
+                        status: reqwest::StatusCode::NOT_MODIFIED,
+                        outcome: None,
+                    });
+                };
+                self.store(key, response)
+            }
+        };
+        Ok(serde_json::from_str(&response.body)?)
+    }
+
+    /// Caches a response that was just received or revalidated and returns it.
+    fn store(&self, key: Key, response: ValidatedResponse) -> ValidatedResponse {
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        if let Some(previous) = entries.lru.pop(&key) {
+            entries.size -= previous.response.body.len();
+        }
+        if !response.storable || response.body.len() > self.max_size {
+            return response;
+        }
+        entries.size += response.body.len();
+        entries.lru.put(
+            key,
+            Entry {
+                response: response.clone(),
+                validated: Instant::now(),
+            },
+        );
+        while entries.size > self.max_size {
+            let Some((_, entry)) = entries.lru.pop_lru() else {
+                break;
+            };
+            entries.size -= entry.response.body.len();
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> ValidatedResponse {
+        ValidatedResponse {
+            body: body.to_string(),
+            etag: None,
+            last_modified: None,
+            storable: true,
+            max_age: None,
+        }
+    }
+
+    fn key(url: &str) -> Key {
+        ("user".to_string(), url.to_string())
+    }
+
+    #[test]
+    fn drops_least_recently_used_responses() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 10);
+        cache.store(key("a"), response("aaaa"));
+        cache.store(key("b"), response("bbbb"));
+        // Using a makes b the least recently used response
+        cache.entries.lock().unwrap().lru.get(&key("a"));
+        cache.store(key("c"), response("cccc"));
+        let entries = cache.entries.lock().unwrap();
+        assert!(entries.lru.contains(&key("a")));
+        assert!(!entries.lru.contains(&key("b")));
+        assert!(entries.lru.contains(&key("c")));
+        assert_eq!(entries.size, 8);
+    }
+
+    #[test]
+    fn replaces_and_removes_responses() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 10);
+        cache.store(key("a"), response("aaaa"));
+        cache.store(key("a"), response("aaaaaa"));
+        assert_eq!(cache.entries.lock().unwrap().size, 6);
+        let no_store = ValidatedResponse {
+            storable: false,
+            ..response("aa")
+        };
+        cache.store(key("a"), no_store);
+        let entries = cache.entries.lock().unwrap();
+        assert!(entries.lru.is_empty());
+        assert_eq!(entries.size, 0);
+    }
+}
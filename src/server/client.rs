@@ -0,0 +1,387 @@
+//! The HTTP client used to talk to the upstream FHIR server.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::de::DeserializeOwned;
+
+mod auth;
+mod retry;
+
+pub use auth::Auth;
+pub use retry::{RequestError, RetryPolicy};
+
+/// A response body together with the validators needed to ask the FHIR
+/// server whether it has changed.
+#[derive(Debug, Clone)]
+pub struct ValidatedResponse {
+    pub body: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// `false` if the server forbids caching with `Cache-Control: no-store`
+    pub storable: bool,
+    /// How long the response may be reused without revalidation according to
+    /// `Cache-Control: max-age`, zero for `no-cache`. `None` if the server
+    /// does not say.
+    pub max_age: Option<Duration>,
+}
+
+impl ValidatedResponse {
+    /// Reads `storable` and `max_age` from a `Cache-Control` header. `private`
+    /// responses are storable since the response cache is per user.
+    fn cache_control(value: Option<&str>) -> (bool, Option<Duration>) {
+        let directives = value
+            .unwrap_or_default()
+            .split(',')
+            .map(|directive| directive.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let storable = !directives.iter().any(|directive| directive == "no-store");
+        let max_age = if directives.iter().any(|directive| directive == "no-cache") {
+            Some(Duration::ZERO)
+        } else {
+            directives.iter().find_map(|directive| {
+                let seconds = directive.strip_prefix("max-age=")?;
+                Some(Duration::from_secs(seconds.trim_matches('"').parse().ok()?))
+            })
+        };
+        (storable, max_age)
+    }
+}
+
+/// Client for the upstream FHIR server. It is created once at startup and
+/// shared by all server functions, so every request goes through the same
+/// connection pool. Cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct FhirClient {
+    http: reqwest::Client,
+    base_url: String,
+    auth: Auth,
+    retry: RetryPolicy,
+}
+
+impl FhirClient {
+    /// Creates a client from the environment:
+    ///
+    /// - `FHIR_BASE_URL`: base URL of the FHIR server, defaults to `http://127.0.0.1:8081/fhir`
+    /// - `FHIR_AUTH` and friends: see [`Auth::from_env`]
+    /// - `FHIR_TIMEOUT`: request timeout in seconds, defaults to 30
+    /// - `FHIR_CONNECT_TIMEOUT`: timeout for establishing a connection in seconds, defaults to 10
+    /// - `FHIR_RETRIES` and friends: see [`RetryPolicy::from_env`]
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_with_prefix("FHIR")
+    }
+
+    /// Like [`FhirClient::from_env`], but reads the variables starting with
+    /// `prefix` instead of `FHIR`, e.g. `AUDIT_FHIR_BASE_URL`.
+    pub fn from_env_with_prefix(prefix: &str) -> anyhow::Result<Self> {
+        let base_url = std::env::var(format!("{prefix}_BASE_URL"))
+            .unwrap_or("http://127.0.0.1:8081/fhir".into());
+        reqwest::Url::parse(&base_url)
+            .with_context(|| format!("{prefix}_BASE_URL is not a valid URL"))?;
+        let timeout = match std::env::var(format!("{prefix}_TIMEOUT")) {
+            Ok(timeout) => timeout
+                .parse()
+                .with_context(|| format!("{prefix}_TIMEOUT must be a number of seconds"))?,
+            Err(_) => 30,
+        };
+        let connect_timeout = match std::env::var(format!("{prefix}_CONNECT_TIMEOUT")) {
+            Ok(timeout) => timeout
+                .parse()
+                .with_context(|| format!("{prefix}_CONNECT_TIMEOUT must be a number of seconds"))?,
+            Err(_) => 10,
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/fhir+json"),
+        );
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(Duration::from_secs(timeout))
+            .connect_timeout(Duration::from_secs(connect_timeout))
+            .build()?;
+
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth: Auth::from_env(prefix)?,
+            retry: RetryPolicy::from_env(prefix)?,
+        })
+    }
+
+    /// Creates a client for `base_url` without authentication, e.g. for a
+    /// mock server in tests.
+    #[cfg(test)]
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth: Auth::None,
+            retry: RetryPolicy::without_retries(),
+        }
+    }
+
+    /// Returns the absolute URL for a path relative to the FHIR base URL,
+    /// e.g. `Patient/123`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    async fn request(&self, url: &str) -> reqwest::Result<reqwest::RequestBuilder> {
+        self.auth.apply(&self.http, self.http.get(url)).await
+    }
+
+    /// Sends a GET request to `url`, after `build` added its headers. If the
+    /// FHIR server rejects the access token, e.g. because it was revoked, the
+    /// request is sent once more with a new token.
+    async fn send<F>(&self, url: &str, build: F) -> Result<reqwest::Response, RequestError>
+    where
+        F: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+    {
+        let send = || async { build(self.request(url).await?).send().await };
+        let response = self.retry.send(send).await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED && self.auth.invalidate().await {
+            tracing::debug!("FHIR server rejected the access token, retrying with a new one");
+            return self.retry.send(send).await;
+        }
+        Ok(response)
+    }
+
+    /// Creates a resource, e.g. an `AuditEvent`, by POSTing it to its type endpoint.
+    pub async fn create(
+        &self,
+        resource_type: &str,
+        resource: &serde_json::Value,
+    ) -> reqwest::Result<()> {
+        let request = self.http.post(self.url(resource_type));
+        self.auth
+            .apply(&self.http, request)
+            .await?
+            .header(header::CONTENT_TYPE, "application/fhir+json")
+            .json(resource)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Fetches the given absolute URL and deserializes the JSON response.
+    /// Transient failures are retried, see [`RetryPolicy::send`].
+    pub async fn get<T>(&self, url: &str) -> Result<T, RequestError>
+    where
+        T: DeserializeOwned,
+    {
+        let response = self.send(url, |request| request).await?;
+        let body = RequestError::check(response).await?.text().await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fetches the given absolute URL unless it has not changed since the
+    /// response with the validators `etag` and `last_modified` was received,
+    /// in which case `None` is returned.
+    pub async fn get_if_changed(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Option<ValidatedResponse>, RequestError> {
+        let response = self
+            .send(url, |mut request| {
+                if let Some(etag) = etag {
+                    request = request.header(header::IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = last_modified {
+                    request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+                }
+                request
+            })
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = RequestError::check(response).await?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(header::ETAG);
+        let last_modified = header(header::LAST_MODIFIED);
+        let (storable, max_age) =
+            ValidatedResponse::cache_control(header(header::CACHE_CONTROL).as_deref());
+        Ok(Some(ValidatedResponse {
+            body: response.text().await?,
+            etag,
+            last_modified,
+            storable,
+            max_age,
+        }))
+    }
+
+    /// Turns a `Bundle.link` URL into a page token, which is the link relative
+    /// to the FHIR base URL. Only the part after the base path is kept because
+    /// the FHIR server builds its links from its own base URL, which differs
+    /// from ours when it is reached through a proxy or a Docker network.
+    pub fn page_token(&self, link: &str) -> Option<String> {
+        let link = reqwest::Url::parse(link).ok()?;
+        let base = reqwest::Url::parse(&self.base_url).ok()?;
+        let path = link
+            .path()
+            .strip_prefix(base.path().trim_end_matches('/'))?
+            .trim_start_matches('/');
+        match link.query() {
+            Some(query) => Some(format!("{path}?{query}")),
+            None => Some(path.to_string()),
+        }
+    }
+
+    /// Resolves a page token created by [`FhirClient::page_token`] back into a
+    /// URL. Returns `None` if the token tries to leave the FHIR base URL. The
+    /// URL is checked after normalization, since the URL parser also resolves
+    /// encoded dot segments like `%2e%2e` and treats `\` like `/`.
+    pub fn page_url(&self, token: &str) -> Option<String> {
+        let base = reqwest::Url::parse(&self.base_url).ok()?;
+        let url = reqwest::Url::parse(&self.url(token)).ok()?;
+        let base_path = format!("{}/", base.path().trim_end_matches('/'));
+        (url.origin() == base.origin() && url.path().starts_with(&base_path)).then(|| url.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cache_control() {
+        let cache_control = ValidatedResponse::cache_control;
+        assert_eq!(cache_control(None), (true, None));
+        assert_eq!(
+            cache_control(Some("private, max-age=30")),
+            (true, Some(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            cache_control(Some("max-age=30, No-Cache")),
+            (true, Some(Duration::ZERO))
+        );
+        assert_eq!(cache_control(Some("no-store")), (false, None));
+    }
+
+    #[tokio::test]
+    async fn retries_with_new_token_after_401() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for access_token in ["revoked", "fresh"] {
+            Mock::given(method("POST"))
+                .and(path("/token"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "access_token": access_token, "expires_in": 300 }),
+                ))
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(header("authorization", "Bearer revoked"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/fhir/Patient/1"))
+            .and(header("authorization", "Bearer fresh"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "resourceType": "Patient", "id": "1" })),
+            )
+            .mount(&server)
+            .await;
+
+        let client = FhirClient {
+            auth: Auth::ClientCredentials(std::sync::Arc::new(auth::ClientCredentials::new(
+                format!("{}/token", server.uri()),
+                "scout".into(),
+                "secret".into(),
+                None,
+            ))),
+            ..FhirClient::new(&format!("{}/fhir", server.uri()))
+        };
+        let patient = client
+            .get::<serde_json::Value>(&client.url("Patient/1"))
+            .await
+            .unwrap();
+        assert_eq!(patient["id"], "1");
+    }
+
+    #[test]
+    fn page_token_is_relative_to_base_path() {
+        let client = FhirClient::new("http://localhost:8080/fhir");
+        assert_eq!(
+            client.page_token("http://blaze:8080/fhir/Patient?_page=2&_count=10"),
+            Some("Patient?_page=2&_count=10".to_string())
+        );
+        assert_eq!(
+            client.page_token("http://blaze:8080/fhir/__page/abc"),
+            Some("__page/abc".to_string())
+        );
+    }
+
+    #[test]
+    fn page_token_rejects_links_outside_base_path() {
+        let client = FhirClient::new("http://localhost:8080/fhir");
+        assert_eq!(client.page_token("http://blaze:8080/other/Patient"), None);
+        assert_eq!(client.page_token("not a url"), None);
+    }
+
+    #[test]
+    fn page_url_round_trips_token() {
+        let client = FhirClient::new("http://localhost:8080/fhir");
+        let token = client
+            .page_token("http://blaze:8080/fhir/Patient?_page=2")
+            .unwrap();
+        assert_eq!(
+            client.page_url(&token),
+            Some("http://localhost:8080/fhir/Patient?_page=2".to_string())
+        );
+    }
+
+    #[test]
+    fn page_url_rejects_escaping_tokens() {
+        let client = FhirClient::new("http://localhost:8080/fhir");
+        for token in [
+            "../admin",
+            "Patient/../../admin",
+            "%2e%2e/%2e%2e/admin",
+            ".%2E/admin",
+            "%2E%2e/admin",
+            "..\\admin",
+            "Patient\\..\\..\\admin",
+        ] {
+            assert_eq!(client.page_url(token), None, "{token}");
+        }
+        // `..` in the query is harmless
+        assert!(client.page_url("Patient?name=..").is_some());
+    }
+
+    #[test]
+    fn page_url_stays_on_fhir_server() {
+        let client = FhirClient::new("http://localhost:8080/fhir");
+        for token in [
+            "http://evil.example/fhir",
+            "//evil.example/fhir",
+            "@evil.example",
+        ] {
+            let url = client.page_url(token).unwrap();
+            assert!(url.starts_with("http://localhost:8080/fhir/"), "{url}");
+        }
+    }
+}
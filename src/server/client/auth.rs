@@ -0,0 +1,285 @@
+//! Authentication strategies for requests to the upstream FHIR server.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// Tokens are refreshed this long before they actually expire, so a request
+/// never leaves with a token that expires while it is in flight.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// How requests to the FHIR server are authenticated.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    None,
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    /// A static bearer token
+    Bearer(String),
+    /// OAuth2 client credentials grant, e.g. against Keycloak
+    ClientCredentials(Arc<ClientCredentials>),
+}
+
+impl Auth {
+    /// Reads the authentication strategy from the environment variables
+    /// starting with `prefix`, e.g. `FHIR`. `FHIR_AUTH` selects the strategy
+    /// and defaults to `basic` if `FHIR_USERNAME` is set and `none` otherwise:
+    ///
+    /// - `none`
+    /// - `basic`: `FHIR_USERNAME` and `FHIR_PASSWORD`
+    /// - `bearer`: `FHIR_TOKEN`
+    /// - `oauth2`: `FHIR_TOKEN_URL`, `FHIR_CLIENT_ID`, `FHIR_CLIENT_SECRET` and optionally `FHIR_SCOPE`
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let optional = |name: &str| std::env::var(format!("{prefix}_{name}")).ok();
+        let var =
+            |name: &str| optional(name).with_context(|| format!("{prefix}_{name} is not set"));
+        let strategy = optional("AUTH").unwrap_or_else(|| {
+            if optional("USERNAME").is_some() {
+                "basic".into()
+            } else {
+                "none".into()
+            }
+        });
+        match strategy.as_str() {
+            "none" => Ok(Auth::None),
+            "basic" => Ok(Auth::Basic {
+                username: var("USERNAME")?,
+                password: optional("PASSWORD"),
+            }),
+            "bearer" => Ok(Auth::Bearer(var("TOKEN")?)),
+            "oauth2" => Ok(Auth::ClientCredentials(Arc::new(ClientCredentials::new(
+                var("TOKEN_URL")?,
+                var("CLIENT_ID")?,
+                var("CLIENT_SECRET")?,
+                optional("SCOPE"),
+            )))),
+            other => anyhow::bail!(
+                "Unknown {prefix}_AUTH \"{other}\", expected one of none, basic, bearer or oauth2"
+            ),
+        }
+    }
+
+    /// Adds the credentials to a request. `http` is used to fetch a new
+    /// access token if necessary.
+    pub async fn apply(
+        &self,
+        http: &reqwest::Client,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::RequestBuilder> {
+        Ok(match self {
+            Auth::None => request,
+            Auth::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::ClientCredentials(credentials) => {
+                request.bearer_auth(credentials.access_token(http).await?)
+            }
+        })
+    }
+
+    /// Drops the cached access token after the FHIR server rejected it, e.g.
+    /// because it was revoked. Returns whether a new token will be used for
+    /// the next request, which is only the case for the client credentials
+    /// grant.
+    pub async fn invalidate(&self) -> bool {
+        match self {
+            Auth::ClientCredentials(credentials) => {
+                *credentials.token.lock().await = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc6749#section-5.1
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc6749#section-4.4
+#[derive(Debug)]
+pub struct ClientCredentials {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    token: tokio::sync::Mutex<Option<CachedToken>>,
+}
+
+impl ClientCredentials {
+    pub fn new(
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        scope: Option<String>,
+    ) -> Self {
+        Self {
+            token_url,
+            client_id,
+            client_secret,
+            scope,
+            token: Default::default(),
+        }
+    }
+
+    /// Returns the cached access token or requests a new one if it is about to
+    /// expire. The lock is held during the refresh so concurrent requests wait
+    /// for a single token request instead of each starting their own.
+    async fn access_token(&self, http: &reqwest::Client) -> reqwest::Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(ref cached) = *token {
+            if cached.expires_at > Instant::now() + EXPIRY_MARGIN {
+                return Ok(cached.access_token.clone());
+            }
+        }
+
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(ref scope) = self.scope {
+            form.push(("scope", scope));
+        }
+        let response = http
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+        // Without expires_in the token is only used for a single request
+        let expires_in = Duration::from_secs(response.expires_in.unwrap_or_default());
+        tracing::debug!("Fetched new FHIR access token valid for {expires_in:?}");
+        *token = Some(CachedToken {
+            access_token: response.access_token.clone(),
+            expires_at: Instant::now() + expires_in,
+        });
+        Ok(response.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    fn credentials(server: &MockServer) -> ClientCredentials {
+        ClientCredentials::new(
+            format!("{}/token", server.uri()),
+            "scout".into(),
+            "secret".into(),
+            Some("fhir".into()),
+        )
+    }
+
+    fn token(access_token: &str, expires_in: u64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": expires_in,
+        }))
+    }
+
+    #[tokio::test]
+    async fn fetches_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .and(body_string_contains("scope=fhir"))
+            .respond_with(token("first", 300))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let token = credentials(&server)
+            .access_token(&reqwest::Client::new())
+            .await
+            .unwrap();
+        assert_eq!(token, "first");
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(token("first", 300))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let credentials = credentials(&server);
+        let http = reqwest::Client::new();
+        assert_eq!(credentials.access_token(&http).await.unwrap(), "first");
+        assert_eq!(credentials.access_token(&http).await.unwrap(), "first");
+    }
+
+    #[tokio::test]
+    async fn refreshes_token_before_expiry() {
+        let server = MockServer::start().await;
+        // Expires within EXPIRY_MARGIN, so it is never reused
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(token("first", 10))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(token("second", 300))
+            .mount(&server)
+            .await;
+        let credentials = credentials(&server);
+        let http = reqwest::Client::new();
+        assert_eq!(credentials.access_token(&http).await.unwrap(), "first");
+        assert_eq!(credentials.access_token(&http).await.unwrap(), "second");
+    }
+
+    #[tokio::test]
+    async fn fails_on_token_endpoint_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        let error = credentials(&server)
+            .access_token(&reqwest::Client::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn invalidate_drops_cached_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(token("first", 300))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let auth = Auth::ClientCredentials(Arc::new(credentials(&server)));
+        let Auth::ClientCredentials(ref credentials) = auth else {
+            unreachable!()
+        };
+        let http = reqwest::Client::new();
+        credentials.access_token(&http).await.unwrap();
+        assert!(auth.invalidate().await);
+        credentials.access_token(&http).await.unwrap();
+        assert!(!Auth::None.invalidate().await);
+    }
+}
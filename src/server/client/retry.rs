@@ -0,0 +1,376 @@
+//! Retries and a circuit breaker for requests to the upstream FHIR server.
+
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use reqwest::{header, Response, StatusCode};
+
+use crate::fhir;
+
+/// The delay before the first retry, doubled for every further retry.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+/// A server that asks for a longer `Retry-After` is treated as unavailable
+/// instead of keeping the user waiting.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Error of a request to the FHIR server.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The server could not be reached or was overloaded even after retrying,
+    /// or the circuit breaker is open.
+    Unavailable,
+    /// The server answered with an error status, usually explaining it with
+    /// an `OperationOutcome`.
+    Status {
+        status: StatusCode,
+        outcome: Option<fhir::OperationOutcome>,
+    },
+    /// The response is not the expected JSON.
+    Parse(serde_json::Error),
+    Http(reqwest::Error),
+}
+
+impl RequestError {
+    /// Turns an error status into [`RequestError::Status`] and passes other
+    /// responses through.
+    pub async fn check(response: Response) -> Result<Response, RequestError> {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(response);
+        }
+        let outcome = response
+            .json::<fhir::OperationOutcome>()
+            .await
+            .ok()
+            .filter(|outcome| !outcome.issue.is_empty());
+        Err(RequestError::Status { status, outcome })
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Unavailable => write!(f, "FHIR server unavailable"),
+            RequestError::Status {
+                status,
+                outcome: Some(outcome),
+            } => write!(f, "FHIR server responded with {status}: {outcome}"),
+            RequestError::Status { status, .. } => write!(f, "FHIR server responded with {status}"),
+            RequestError::Parse(e) => write!(f, "Invalid response of the FHIR server: {e}"),
+            RequestError::Http(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RequestError::Unavailable | RequestError::Status { .. } => None,
+            RequestError::Parse(e) => Some(e),
+            RequestError::Http(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(e: reqwest::Error) -> Self {
+        RequestError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for RequestError {
+    fn from(e: serde_json::Error) -> Self {
+        RequestError::Parse(e)
+    }
+}
+
+/// How often idempotent requests are retried, and when to stop sending
+/// requests to a server that keeps failing.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    retries: u32,
+    /// Failed requests in a row after which the circuit breaker opens
+    threshold: u32,
+    /// How long the circuit breaker stays open
+    cooldown: Duration,
+    breaker: Arc<Mutex<Breaker>>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl RetryPolicy {
+    /// Reads the policy from the environment variables starting with `prefix`,
+    /// e.g. `FHIR`:
+    ///
+    /// - `FHIR_RETRIES`: retries of a failed request, defaults to 3
+    /// - `FHIR_CIRCUIT_THRESHOLD`: failed requests in a row after which no
+    ///   requests are sent for a while, defaults to 5
+    /// - `FHIR_CIRCUIT_COOLDOWN`: seconds for which no requests are sent,
+    ///   defaults to 30
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        fn number<T: FromStr>(prefix: &str, name: &str, default: T) -> anyhow::Result<T> {
+            match std::env::var(format!("{prefix}_{name}")) {
+                Ok(value) => value
+                    .parse()
+                    .ok()
+                    .with_context(|| format!("{prefix}_{name} must be a number")),
+                Err(_) => Ok(default),
+            }
+        }
+        Ok(Self {
+            retries: number(prefix, "RETRIES", 3)?,
+            threshold: number(prefix, "CIRCUIT_THRESHOLD", 5u32)?.max(1),
+            cooldown: Duration::from_secs(number(prefix, "CIRCUIT_COOLDOWN", 30)?),
+            breaker: Default::default(),
+        })
+    }
+
+    /// A policy that neither retries nor opens the circuit breaker, for tests.
+    #[cfg(test)]
+    pub fn without_retries() -> Self {
+        Self {
+            retries: 0,
+            threshold: u32::MAX,
+            cooldown: Duration::ZERO,
+            breaker: Default::default(),
+        }
+    }
+
+    /// Sends the request built by `send` and retries it with exponential
+    /// backoff if the server cannot be reached or answers with 429, 502, 503
+    /// or 504, honouring `Retry-After`. Only use this for idempotent requests.
+    /// Other server errors neither count as failures nor close the circuit
+    /// breaker.
+    pub async fn send<F, Fut>(&self, send: F) -> Result<Response, RequestError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = reqwest::Result<Response>>,
+    {
+        if self
+            .breaker
+            .lock()
+            .unwrap()
+            .open_until
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return Err(RequestError::Unavailable);
+        }
+        let mut attempt = 0;
+        loop {
+            let delay = match send().await {
+                Ok(response) if is_transient(response.status()) => retry_after(&response),
+                Ok(response) if response.status().is_server_error() => return Ok(response),
+                Ok(response) => {
+                    self.succeeded();
+                    return Ok(response);
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => None,
+                Err(e) => return Err(e.into()),
+            };
+            let delay = delay.unwrap_or_else(|| backoff(attempt));
+            if attempt >= self.retries || delay > MAX_DELAY {
+                self.failed();
+                return Err(RequestError::Unavailable);
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.failures = 0;
+        breaker.open_until = None;
+    }
+
+    /// Once the cooldown is over, requests are let through again. The first
+    /// one that fails opens the breaker right away.
+    fn failed(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.failures += 1;
+        if breaker.failures >= self.threshold {
+            tracing::warn!(
+                "FHIR server failed {} times in a row, pausing requests for {:?}",
+                breaker.failures,
+                self.cooldown
+            );
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// The delay before a retry without `Retry-After`.
+fn backoff(attempt: u32) -> Duration {
+    BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt))
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// The `Retry-After` header, either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = jiff::fmt::rfc2822::parse(value).ok()?.timestamp();
+    let delay = date.duration_since(jiff::Timestamp::now());
+    Some(Duration::try_from(delay).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    fn response(retry_after: &str) -> Response {
+        http::Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::RETRY_AFTER, retry_after)
+            .body("")
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn checks_status_and_reads_outcome() {
+        let response = |status: u16, body: &str| -> Response {
+            http::Response::builder()
+                .status(status)
+                .body(body.to_string())
+                .unwrap()
+                .into()
+        };
+        assert!(RequestError::check(response(200, "{}")).await.is_ok());
+        assert!(RequestError::check(response(304, "")).await.is_ok());
+
+        let outcome = r#"{"resourceType": "OperationOutcome",
+            "issue": [{"severity": "error", "code": "invalid", "diagnostics": "Unknown parameter"}]}"#;
+        match RequestError::check(response(400, outcome)).await {
+            Err(RequestError::Status {
+                status,
+                outcome: Some(outcome),
+            }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(outcome.issue.len(), 1);
+            }
+            result => panic!("unexpected {result:?}"),
+        }
+        assert!(matches!(
+            RequestError::check(response(502, "<html>Bad Gateway</html>")).await,
+            Err(RequestError::Status { outcome: None, .. })
+        ));
+    }
+
+    #[test]
+    fn reads_retry_after() {
+        assert_eq!(retry_after(&response("3")), Some(Duration::from_secs(3)));
+        let past = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(retry_after(&response(past)), Some(Duration::ZERO));
+        let future = jiff::fmt::rfc2822::to_string(
+            &(jiff::Timestamp::now() + jiff::SignedDuration::from_secs(60))
+                .to_zoned(jiff::tz::TimeZone::UTC),
+        )
+        .unwrap();
+        let delay = retry_after(&response(&future)).unwrap();
+        assert!(delay > Duration::from_secs(50) && delay <= Duration::from_secs(60));
+        assert_eq!(retry_after(&response("soon")), None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        std::env::set_var("RANGE_TEST_RETRIES", "2");
+        assert_eq!(RetryPolicy::from_env("RANGE_TEST").unwrap().retries, 2);
+        std::env::set_var("RANGE_TEST_RETRIES", "4294967296");
+        assert!(RetryPolicy::from_env("RANGE_TEST").is_err());
+        std::env::set_var("RANGE_TEST_RETRIES", "-1");
+        assert!(RetryPolicy::from_env("RANGE_TEST").is_err());
+    }
+
+    /// A mock server that answers `/unavailable` with 503, `/broken` with 500
+    /// and `/ok` with 200, and a policy without retries whose breaker opens
+    /// after two failures in a row.
+    async fn breaker() -> (MockServer, RetryPolicy) {
+        let server = MockServer::start().await;
+        for (route, status) in [("/unavailable", 503), ("/broken", 500), ("/ok", 200)] {
+            Mock::given(path(route))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+        }
+        let policy = RetryPolicy {
+            retries: 0,
+            threshold: 2,
+            cooldown: Duration::from_secs(60),
+            breaker: Default::default(),
+        };
+        (server, policy)
+    }
+
+    async fn get(
+        server: &MockServer,
+        policy: &RetryPolicy,
+        route: &str,
+    ) -> Result<Response, RequestError> {
+        let request = reqwest::Client::new().get(format!("{}{route}", server.uri()));
+        policy
+            .send(move || request.try_clone().unwrap().send())
+            .await
+    }
+
+    #[tokio::test]
+    async fn opens_breaker_after_failures_in_a_row() {
+        let (server, policy) = breaker().await;
+        let send = |route| get(&server, &policy, route);
+
+        assert!(matches!(
+            send("/unavailable").await,
+            Err(RequestError::Unavailable)
+        ));
+        // A server error is passed on, but does not close the breaker
+        let broken = send("/broken").await.unwrap();
+        assert_eq!(broken.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(matches!(
+            send("/unavailable").await,
+            Err(RequestError::Unavailable)
+        ));
+        let requests = server.received_requests().await.unwrap().len();
+        assert!(matches!(send("/ok").await, Err(RequestError::Unavailable)));
+        assert_eq!(server.received_requests().await.unwrap().len(), requests);
+    }
+
+    #[tokio::test]
+    async fn success_resets_failures() {
+        let (server, policy) = breaker().await;
+        let send = |route| get(&server, &policy, route);
+
+        assert!(send("/unavailable").await.is_err());
+        assert!(send("/ok").await.is_ok());
+        assert!(send("/unavailable").await.is_err());
+        assert!(send("/ok").await.is_ok());
+    }
+
+    #[test]
+    fn backoff_doubles_without_overflowing() {
+        assert_eq!(backoff(0), BASE_DELAY);
+        assert_eq!(backoff(2), BASE_DELAY * 4);
+        assert!(backoff(100) > MAX_DELAY);
+    }
+}
@@ -0,0 +1,362 @@
+//! OpenID Connect authorization code flow for logging users into Scout.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use base64::Engine;
+use serde::Deserialize;
+
+use super::User;
+
+/// https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    end_session_endpoint: Option<String>,
+}
+
+/// https://openid.net/specs/openid-connect-core-1_0.html#TokenResponse
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+/// The identity of a user after a successful login.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub user: User,
+    /// Passed as `id_token_hint` when logging out at the provider
+    pub id_token: Option<String>,
+}
+
+/// A confidential OIDC client. The provider metadata is discovered on first
+/// use, so Scout can start while the identity provider is still down.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    http: reqwest::Client,
+    issuer_url: String,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    scopes: String,
+    roles_claim: String,
+    metadata: Arc<tokio::sync::OnceCell<ProviderMetadata>>,
+}
+
+impl OidcProvider {
+    /// Creates a provider from `OIDC_ISSUER_URL`, `OIDC_CLIENT_ID`,
+    /// `OIDC_CLIENT_SECRET` and `OIDC_REDIRECT_URL`. `OIDC_SCOPES` defaults to
+    /// `openid profile email` and `OIDC_ROLES_CLAIM`, the dot separated path
+    /// of the userinfo claim holding the user's roles, defaults to `roles`.
+    /// The issuer must be reached over https, see [`OidcProvider::exchange`].
+    pub fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str| std::env::var(name).with_context(|| format!("{name} is not set"));
+        let issuer_url = var("OIDC_ISSUER_URL")?.trim_end_matches('/').to_string();
+        anyhow::ensure!(
+            is_tls_or_loopback(&issuer_url),
+            "OIDC_ISSUER_URL must be an https URL"
+        );
+        Ok(Self {
+            http: reqwest::Client::new(),
+            issuer_url,
+            client_id: var("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET")?,
+            redirect_url: var("OIDC_REDIRECT_URL")?,
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or("openid profile email".into()),
+            roles_claim: std::env::var("OIDC_ROLES_CLAIM").unwrap_or("roles".into()),
+            metadata: Default::default(),
+        })
+    }
+
+    /// Whether the session cookie needs the `Secure` attribute.
+    pub fn is_https(&self) -> bool {
+        self.redirect_url.starts_with("https://")
+    }
+
+    async fn metadata(&self) -> anyhow::Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+                let metadata = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<ProviderMetadata>()
+                    .await
+                    .with_context(|| format!("Failed to discover OIDC provider at {url}"))?;
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Returns the URL the browser is sent to in order to log in.
+    pub async fn authorization_url(&self, state: &str, nonce: &str) -> anyhow::Result<String> {
+        let metadata = self.metadata().await?;
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+            ],
+        )?;
+        Ok(url.into())
+    }
+
+    /// Exchanges the authorization code from the callback for tokens and
+    /// looks up the user. The claims of the ID token are validated with
+    /// [`validate_id_claims`], its signature is not: the ID token comes
+    /// directly from the token endpoint over TLS, which the OIDC spec allows
+    /// in place of the signature, so a token endpoint without https is
+    /// refused. The user's claims are taken from the userinfo endpoint.
+    pub async fn exchange(&self, code: &str, nonce: &str) -> anyhow::Result<Identity> {
+        let metadata = self.metadata().await?;
+        anyhow::ensure!(
+            is_tls_or_loopback(&metadata.token_endpoint),
+            "OIDC token endpoint must be an https URL"
+        );
+        let tokens = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+        let id_token = tokens
+            .id_token
+            .as_deref()
+            .context("token response is missing the ID token")?;
+        let id_claims = id_token_claims(id_token)?;
+        validate_id_claims(
+            &id_claims,
+            &self.issuer_url,
+            &self.client_id,
+            nonce,
+            jiff::Timestamp::now(),
+        )?;
+        let claims = self
+            .http
+            .get(&metadata.userinfo_endpoint)
+            .bearer_auth(&tokens.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+
+        let subject = claims["sub"]
+            .as_str()
+            .context("userinfo response is missing the sub claim")?
+            .to_string();
+        anyhow::ensure!(
+            id_claims["sub"] == subject,
+            "userinfo response belongs to another user than the ID token"
+        );
+        let name = ["name", "preferred_username", "email"]
+            .iter()
+            .find_map(|claim| claims[claim].as_str())
+            .unwrap_or(&subject)
+            .to_string();
+        let roles = self
+            .roles_claim
+            .split('.')
+            .fold(&claims, |claims, key| &claims[key])
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|role| role.as_str().map(String::from))
+            .collect();
+        Ok(Identity {
+            user: User {
+                subject,
+                name,
+                roles,
+            },
+            id_token: tokens.id_token,
+        })
+    }
+
+    /// Returns the URL that ends the session at the provider, if it supports
+    /// RP-initiated logout.
+    pub async fn end_session_url(&self, id_token: Option<&str>) -> Option<String> {
+        let endpoint = self.metadata().await.ok()?.end_session_endpoint.as_ref()?;
+        let mut params = vec![("client_id", self.client_id.as_str())];
+        if let Some(id_token) = id_token {
+            params.push(("id_token_hint", id_token));
+        }
+        reqwest::Url::parse_with_params(endpoint, params)
+            .ok()
+            .map(Into::into)
+    }
+}
+
+/// Whether `url` uses https, or plain http to the local machine, e.g. an
+/// identity provider started for development.
+fn is_tls_or_loopback(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    match url.scheme() {
+        "https" => true,
+        "http" => matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+        _ => false,
+    }
+}
+
+/// Checks the claims of an ID token as required by
+/// https://openid.net/specs/openid-connect-core-1_0.html#IDTokenValidation:
+/// it must be issued by `issuer` for `client_id`, must not be expired and
+/// must contain the `nonce` of the login.
+fn validate_id_claims(
+    claims: &serde_json::Value,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: jiff::Timestamp,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        claims["iss"]
+            .as_str()
+            .is_some_and(|iss| iss.trim_end_matches('/') == issuer),
+        "ID token was issued by another provider"
+    );
+    let audiences = match &claims["aud"] {
+        serde_json::Value::String(aud) => vec![aud.as_str()],
+        serde_json::Value::Array(auds) => auds.iter().filter_map(|aud| aud.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    anyhow::ensure!(
+        audiences.contains(&client_id),
+        "ID token was issued for another client"
+    );
+    // With several audiences the authorized party tells which one the token is for
+    if audiences.len() > 1 || !claims["azp"].is_null() {
+        anyhow::ensure!(
+            claims["azp"] == client_id,
+            "ID token was issued for another client"
+        );
+    }
+    anyhow::ensure!(
+        claims["exp"]
+            .as_i64()
+            .is_some_and(|exp| exp > now.as_second()),
+        "ID token is expired"
+    );
+    anyhow::ensure!(
+        claims["nonce"] == nonce,
+        "ID token does not contain the nonce of the login"
+    );
+    Ok(())
+}
+
+/// Decodes the claims of an ID token without validating its signature.
+fn id_token_claims(id_token: &str) -> anyhow::Result<serde_json::Value> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .context("ID token is not a JWT")?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("ID token is not a JWT")?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_id_token_claims() {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"sub":"alice","nonce":"abc"}"#);
+        let claims = id_token_claims(&format!("header.{payload}.signature")).unwrap();
+        assert_eq!(claims["nonce"], "abc");
+        assert!(id_token_claims("not a jwt").is_err());
+    }
+
+    const ISSUER: &str = "https://idp.example.org/realms/scout";
+
+    fn claims() -> serde_json::Value {
+        serde_json::json!({
+            "iss": ISSUER,
+            "aud": "scout",
+            "sub": "alice",
+            "exp": 1_700_000_300,
+            "nonce": "abc",
+        })
+    }
+
+    fn validate(claims: &serde_json::Value) -> anyhow::Result<()> {
+        let now = jiff::Timestamp::from_second(1_700_000_000).unwrap();
+        validate_id_claims(claims, ISSUER, "scout", "abc", now)
+    }
+
+    #[test]
+    fn accepts_valid_id_token_claims() {
+        validate(&claims()).unwrap();
+        let mut claims = claims();
+        claims["aud"] = serde_json::json!(["scout", "account"]);
+        claims["azp"] = "scout".into();
+        validate(&claims).unwrap();
+    }
+
+    #[test]
+    fn rejects_id_tokens_of_other_clients() {
+        let mut claims = claims();
+        claims["aud"] = "other".into();
+        assert!(validate(&claims).is_err());
+        claims["aud"] = serde_json::json!(["scout", "other"]);
+        assert!(validate(&claims).is_err());
+        claims["azp"] = "other".into();
+        assert!(validate(&claims).is_err());
+    }
+
+    #[test]
+    fn rejects_id_tokens_of_other_issuers() {
+        let mut claims = claims();
+        claims["iss"] = "https://evil.example/realms/scout".into();
+        assert!(validate(&claims).is_err());
+        claims.as_object_mut().unwrap().remove("iss");
+        assert!(validate(&claims).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_id_tokens() {
+        let mut claims = claims();
+        claims["exp"] = 1_699_999_999.into();
+        assert!(validate(&claims).is_err());
+        claims.as_object_mut().unwrap().remove("exp");
+        assert!(validate(&claims).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_nonce() {
+        let mut claims = claims();
+        claims["nonce"] = "other".into();
+        assert!(validate(&claims).is_err());
+    }
+
+    #[test]
+    fn requires_tls_except_on_loopback() {
+        assert!(is_tls_or_loopback(ISSUER));
+        assert!(is_tls_or_loopback("http://localhost:8080/realms/scout"));
+        assert!(is_tls_or_loopback("http://127.0.0.1:8080/realms/scout"));
+        assert!(!is_tls_or_loopback("http://idp.example.org/realms/scout"));
+        assert!(!is_tls_or_loopback("not a url"));
+    }
+}
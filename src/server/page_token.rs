@@ -0,0 +1,96 @@
+//! Signed page tokens, so that the browser can only pass back the page tokens
+//! Scout gave it for a search.
+//!
+//! Page tokens contain the FHIR search they continue, so a token made up in
+//! the browser could search by fields the user may not see or drop the
+//! patient filters of the access policy. Each token is therefore signed
+//! together with the user and the search it belongs to. The key is created at
+//! startup, so tokens do not survive a restart, like sessions.
+
+use std::sync::Arc;
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+#[derive(Debug, Clone)]
+pub struct PageTokens {
+    key: Arc<[u8; 32]>,
+}
+
+impl Default for PageTokens {
+    /// Creates a random key.
+    fn default() -> Self {
+        let mut key = [0; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self { key: Arc::new(key) }
+    }
+}
+
+impl PageTokens {
+    fn mac(&self, scope: &str, token: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_slice())
+            .expect("HMAC accepts keys of any length");
+        // The length keeps `scope` and `token` apart
+        mac.update(&(scope.len() as u64).to_be_bytes());
+        mac.update(scope.as_bytes());
+        mac.update(token.as_bytes());
+        mac
+    }
+
+    /// Signs the page token `token` of a search, e.g. from
+    /// [`super::FhirClient::page_token`]. `scope` identifies the user and the
+    /// search.
+    pub fn sign(&self, scope: &str, token: &str) -> String {
+        let signature = self.mac(scope, token).finalize().into_bytes();
+        format!("{}.{token}", BASE64.encode(signature))
+    }
+
+    /// Returns the page token signed with [`PageTokens::sign`], or `None` if
+    /// it was signed for another scope or altered.
+    pub fn verify(&self, scope: &str, signed: &str) -> Option<String> {
+        let (signature, token) = signed.split_once('.')?;
+        let signature = BASE64.decode(signature).ok()?;
+        self.mac(scope, token).verify_slice(&signature).ok()?;
+        Some(token.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_tokens_of_the_same_scope_only() {
+        let tokens = PageTokens::default();
+        let signed = tokens.sign("alice Patient?gender=female", "Patient?_page=2");
+        assert_eq!(
+            tokens.verify("alice Patient?gender=female", &signed),
+            Some("Patient?_page=2".to_string())
+        );
+        assert_eq!(tokens.verify("bob Patient?gender=female", &signed), None);
+        assert_eq!(tokens.verify("alice Patient?gender=male", &signed), None);
+        assert_eq!(
+            PageTokens::default().verify("alice Patient?gender=female", &signed),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_altered_tokens() {
+        let tokens = PageTokens::default();
+        let signed = tokens.sign("alice", "Patient?_page=2");
+        let (signature, _) = signed.split_once('.').unwrap();
+        for forged in [
+            format!("{signature}.Patient?birthdate=ge1990-01-01&_count=1000"),
+            "Patient?_page=2".to_string(),
+            format!(".{signed}"),
+            String::new(),
+        ] {
+            assert_eq!(tokens.verify("alice", &forged), None, "{forged}");
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Role based restrictions on which patients, resource types and fields a
+//! user may see.
+//!
+//! The policy is read from the JSON file at `POLICY_FILE`, which contains a
+//! list of rules like the following. The first rule whose role the user has is
+//! applied and users without a matching role are denied access. The role `*`
+//! matches every user. Patient names are only shown to roles with
+//! `showNames`.
+//!
+//! ```json
+//! [
+//!     {
+//!         "role": "study-nurse",
+//!         "identifierSystems": ["https://www.medizininformatik-initiative.de/fhir/sid/pseudonym"],
+//!         "hiddenFields": ["Patient.name", "Patient.address.line"]
+//!     },
+//!     {
+//!         "role": "auditor",
+//!         "auditLog": true
+//!     },
+//!     {
+//!         "role": "clinician",
+//!         "showNames": true,
+//!         "securityLabels": [{ "system": "http://terminology.hl7.org/CodeSystem/v3-ActReason", "code": "HTEST" }],
+//!         "organizations": ["Organization/charite"],
+//!         "hiddenResourceTypes": ["Procedure"]
+//!     }
+//! ]
+//! ```
+//!
+//! Without `POLICY_FILE` nobody can see patient data, unless
+//! `POLICY_ALLOW_ALL=true` explicitly allows every logged in user to see all
+//! patients except their names, e.g. for local development.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::User;
+
+/// A `meta.security` label, e.g. a confidentiality code.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecurityLabel {
+    pub system: String,
+    pub code: String,
+}
+
+/// Restrictions for the users with a role. Empty lists do not restrict
+/// anything. The patient filters are combined with AND, the entries of each
+/// list with OR.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Rule {
+    pub role: String,
+    /// Only patients with an identifier in one of these systems
+    #[serde(default)]
+    pub identifier_systems: Vec<String>,
+    /// Only patients with one of these `meta.security` labels
+    #[serde(default)]
+    pub security_labels: Vec<SecurityLabel>,
+    /// Only patients managed by one of these organizations, e.g. `Organization/123`
+    #[serde(default)]
+    pub organizations: Vec<String>,
+    /// Resource types that are removed from the patient timeline
+    #[serde(default)]
+    pub hidden_resource_types: Vec<String>,
+    /// Fields that are removed from resources, e.g. `Patient.name` or `Patient.address.line`
+    #[serde(default)]
+    pub hidden_fields: Vec<String>,
+    /// Whether `Patient.name` is shown, it is hidden otherwise
+    #[serde(default)]
+    pub show_names: bool,
+    /// Whether the audit log may be browsed
+    #[serde(default)]
+    pub audit_log: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Arc<Vec<Rule>>,
+}
+
+impl Policy {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(path) = std::env::var("POLICY_FILE") else {
+            let rules = if std::env::var("POLICY_ALLOW_ALL").is_ok_and(|allow| allow == "true") {
+                tracing::warn!("POLICY_ALLOW_ALL is set, all users can see all patients");
+                vec![Rule {
+                    role: "*".into(),
+                    ..Default::default()
+                }]
+            } else {
+                tracing::warn!("POLICY_FILE is not set, nobody can see patient data");
+                Vec::new()
+            };
+            return Ok(Self {
+                rules: Arc::new(rules),
+            });
+        };
+        let file = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read policy file {path}"))?;
+        let rules = serde_json::from_str(&file)
+            .with_context(|| format!("Failed to parse policy file {path}"))?;
+        Ok(Self {
+            rules: Arc::new(rules),
+        })
+    }
+
+    /// Returns the rule that applies to a user, or `None` if the user may not
+    /// see any patient data.
+    pub fn rule(&self, user: &User) -> Option<Rule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.role == "*" || user.roles.contains(&rule.role))
+            .cloned()
+    }
+}
+
+impl Rule {
+    /// FHIR search parameters that let the FHIR server apply the patient
+    /// filters, so that paging and totals are correct. The results still have
+    /// to be checked with [`Rule::allows_patient`], since page tokens come
+    /// from the browser.
+    pub fn patient_search_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if !self.identifier_systems.is_empty() {
+            let systems = self
+                .identifier_systems
+                .iter()
+                .map(|system| format!("{system}|"));
+            params.push(("identifier", systems.collect::<Vec<_>>().join(",")));
+        }
+        if !self.security_labels.is_empty() {
+            let labels = self
+                .security_labels
+                .iter()
+                .map(|label| format!("{}|{}", label.system, label.code));
+            params.push(("_security", labels.collect::<Vec<_>>().join(",")));
+        }
+        if !self.organizations.is_empty() {
+            params.push(("organization", self.organizations.join(",")));
+        }
+        params
+    }
+
+    pub fn allows_patient(&self, patient: &Value) -> bool {
+        let strs = |values: &Value, key: &str| -> Vec<String> {
+            values
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|value| value[key].as_str().map(String::from))
+                .collect()
+        };
+        patient["resourceType"] == "Patient"
+            && (self.identifier_systems.is_empty()
+                || strs(&patient["identifier"], "system")
+                    .iter()
+                    .any(|system| self.identifier_systems.contains(system)))
+            && (self.security_labels.is_empty()
+                || patient["meta"]["security"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .any(|coding| {
+                        self.security_labels.iter().any(|label| {
+                            coding["system"] == label.system.as_str()
+                                && coding["code"] == label.code.as_str()
+                        })
+                    }))
+            && (self.organizations.is_empty()
+                || patient["managingOrganization"]["reference"]
+                    .as_str()
+                    .is_some_and(|reference| self.organizations.iter().any(|o| o == reference)))
+    }
+
+    /// Whether the field, e.g. `Patient.address.city`, or a part of it is
+    /// removed by [`Rule::strip`].
+    pub fn hides(&self, field: &str) -> bool {
+        let overlaps = |hidden: &str| {
+            let (shorter, longer) = if hidden.len() <= field.len() {
+                (hidden, field)
+            } else {
+                (field, hidden)
+            };
+            longer == shorter || longer.starts_with(&format!("{shorter}."))
+        };
+        (!self.show_names && overlaps("Patient.name"))
+            || self.hidden_fields.iter().any(|hidden| overlaps(hidden))
+    }
+
+    pub fn allows_resource_type(&self, resource_type: &str) -> bool {
+        !self
+            .hidden_resource_types
+            .iter()
+            .any(|hidden| hidden == resource_type)
+    }
+
+    /// Removes the hidden fields from a resource, including the extensions of
+    /// primitive fields (`_name`).
+    pub fn strip(&self, resource: &mut Value) {
+        let Some(resource_type) = resource["resourceType"].as_str().map(String::from) else {
+            return;
+        };
+        if resource_type == "Patient" && !self.show_names {
+            remove_path(resource, &["name"]);
+        }
+        for field in &self.hidden_fields {
+            let mut path = field.split('.');
+            if path.next() == Some(resource_type.as_str()) {
+                remove_path(resource, &path.collect::<Vec<_>>());
+            }
+        }
+    }
+}
+
+/// Removes the element at `path` from `value`, descending into every element
+/// of the arrays on the way.
+fn remove_path(value: &mut Value, path: &[&str]) {
+    match (value, path) {
+        (Value::Array(values), _) => values.iter_mut().for_each(|value| remove_path(value, path)),
+        (Value::Object(object), [key]) => {
+            object.remove(*key);
+            object.remove(&format!("_{key}"));
+        }
+        (Value::Object(object), [key, rest @ ..]) => {
+            if let Some(value) = object.get_mut(*key) {
+                remove_path(value, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rule(rule: Value) -> Rule {
+        serde_json::from_value(rule).unwrap()
+    }
+
+    #[test]
+    fn strips_hidden_fields_and_names() {
+        let rule = rule(json!({
+            "role": "study-nurse",
+            "hiddenFields": ["Patient.address.line", "Patient.birthDate"]
+        }));
+        let mut patient = json!({
+            "resourceType": "Patient",
+            "name": [{ "family": "Doe" }],
+            "birthDate": "1970-01-01",
+            "_birthDate": { "extension": [] },
+            "address": [{ "line": ["Main St 1"], "city": "Heidelberg" }, { "city": "Mannheim" }],
+            "gender": "female"
+        });
+        rule.strip(&mut patient);
+        assert_eq!(
+            patient,
+            json!({
+                "resourceType": "Patient",
+                "address": [{ "city": "Heidelberg" }, { "city": "Mannheim" }],
+                "gender": "female"
+            })
+        );
+    }
+
+    #[test]
+    fn shows_names_if_allowed() {
+        let rule = rule(json!({ "role": "clinician", "showNames": true }));
+        let mut patient = json!({ "resourceType": "Patient", "name": [{ "family": "Doe" }] });
+        rule.strip(&mut patient);
+        assert_eq!(patient["name"][0]["family"], "Doe");
+    }
+
+    #[test]
+    fn strip_ignores_fields_of_other_types() {
+        let rule = rule(json!({ "role": "nurse", "hiddenFields": ["Patient.name"] }));
+        let mut condition = json!({ "resourceType": "Condition", "name": "kept" });
+        rule.strip(&mut condition);
+        assert_eq!(condition["name"], "kept");
+    }
+
+    #[test]
+    fn hides_fields_and_their_parts() {
+        let rule = rule(json!({
+            "role": "study-nurse",
+            "hiddenFields": ["Patient.address.line", "Patient.identifier.value"]
+        }));
+        assert!(rule.hides("Patient.name"));
+        assert!(rule.hides("Patient.identifier"));
+        assert!(rule.hides("Patient.address.line"));
+        assert!(rule.hides("Patient.address"));
+        assert!(!rule.hides("Patient.address.city"));
+        assert!(!rule.hides("Patient.gender"));
+        assert!(!rule.hides("Patient.names"));
+    }
+
+    #[test]
+    fn allows_patient_combines_filters() {
+        let rule = rule(json!({
+            "role": "clinician",
+            "identifierSystems": ["urn:pseudonym"],
+            "organizations": ["Organization/a"]
+        }));
+        let patient = |system: &str, organization: &str| {
+            json!({
+                "resourceType": "Patient",
+                "identifier": [{ "system": system, "value": "1" }],
+                "managingOrganization": { "reference": organization }
+            })
+        };
+        assert!(rule.allows_patient(&patient("urn:pseudonym", "Organization/a")));
+        assert!(!rule.allows_patient(&patient("urn:mrn", "Organization/a")));
+        assert!(!rule.allows_patient(&patient("urn:pseudonym", "Organization/b")));
+        assert!(!rule.allows_patient(&json!({ "resourceType": "Observation" })));
+    }
+
+    #[test]
+    fn allows_patient_by_security_label() {
+        let rule = rule(json!({
+            "role": "clinician",
+            "securityLabels": [{ "system": "urn:labels", "code": "study" }]
+        }));
+        let labelled = json!({
+            "resourceType": "Patient",
+            "meta": { "security": [{ "system": "urn:labels", "code": "study" }] }
+        });
+        assert!(rule.allows_patient(&labelled));
+        assert!(!rule.allows_patient(&json!({ "resourceType": "Patient" })));
+    }
+
+    #[test]
+    fn denies_users_without_matching_role() {
+        let user = |roles: &[&str]| User {
+            subject: "alice".into(),
+            name: "Alice".into(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        };
+        let policy = Policy {
+            rules: Arc::new(vec![rule(json!({ "role": "clinician" }))]),
+        };
+        assert!(policy.rule(&user(&["clinician"])).is_some());
+        assert!(policy.rule(&user(&["nurse"])).is_none());
+        let empty = Policy {
+            rules: Arc::new(Vec::new()),
+        };
+        assert!(empty.rule(&user(&["clinician"])).is_none());
+    }
+}
@@ -0,0 +1,234 @@
+//! Login sessions of Scout users, identified by a session cookie.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::distributions::{Alphanumeric, DistString};
+
+use super::oidc::{Identity, OidcProvider};
+
+const COOKIE_NAME: &str = "scout_session";
+/// Holds the `state` of a pending login, so that the callback is only
+/// accepted in the browser that started the login.
+const LOGIN_COOKIE_NAME: &str = "scout_login";
+
+/// A login that was started but whose callback has not arrived yet. Logins
+/// that take longer than this are rejected.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+pub struct PendingLogin {
+    started: Instant,
+    pub return_to: String,
+    /// Must be contained in the ID token
+    pub nonce: String,
+}
+
+#[derive(Debug)]
+struct Session {
+    identity: Identity,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: HashMap<String, PendingLogin>,
+    sessions: HashMap<String, Session>,
+}
+
+/// In-memory store of pending logins and sessions. Sessions do not survive a
+/// restart, users simply have to log in again.
+#[derive(Debug, Clone)]
+pub struct Authentication {
+    /// `None` if authentication is disabled
+    pub provider: Option<OidcProvider>,
+    ttl: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl Authentication {
+    /// Sets up OIDC login from the environment, see [`OidcProvider::from_env`].
+    /// Login can only be turned off explicitly with `AUTH_DISABLED=true`.
+    /// `SESSION_TTL` is the session lifetime in seconds and defaults to 8 hours.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let provider = if std::env::var("AUTH_DISABLED").is_ok_and(|disabled| disabled == "true") {
+            tracing::warn!("Authentication is disabled, everyone can access all patients");
+            None
+        } else {
+            Some(OidcProvider::from_env().context(
+                "OIDC is not configured, set AUTH_DISABLED=true to run without authentication",
+            )?)
+        };
+        let ttl = match std::env::var("SESSION_TTL") {
+            Ok(ttl) => ttl
+                .parse()
+                .context("SESSION_TTL must be a number of seconds")?,
+            Err(_) => 8 * 60 * 60,
+        };
+        Ok(Self {
+            provider,
+            ttl: Duration::from_secs(ttl),
+            state: Default::default(),
+        })
+    }
+
+    fn random_token() -> String {
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 43)
+    }
+
+    /// Registers a new login and returns the `state` and `nonce` parameters
+    /// identifying it. The `state` must also be set as login cookie.
+    pub fn begin_login(&self, return_to: String) -> (String, String) {
+        let token = Self::random_token();
+        let nonce = Self::random_token();
+        let mut state = self.state.lock().unwrap();
+        state
+            .pending
+            .retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        state.pending.insert(
+            token.clone(),
+            PendingLogin {
+                started: Instant::now(),
+                return_to,
+                nonce: nonce.clone(),
+            },
+        );
+        (token, nonce)
+    }
+
+    /// Consumes a pending login, or returns `None` if the `state` parameter is
+    /// unknown or expired or does not match the login cookie of the browser.
+    pub fn finish_login(&self, token: &str, cookie: Option<&str>) -> Option<PendingLogin> {
+        if cookie != Some(token) {
+            return None;
+        }
+        let login = self.state.lock().unwrap().pending.remove(token)?;
+        (login.started.elapsed() < LOGIN_TIMEOUT).then_some(login)
+    }
+
+    /// Creates a session and returns its ID.
+    pub fn create_session(&self, identity: Identity) -> String {
+        let id = Self::random_token();
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.prune(now);
+        state.sessions.insert(
+            id.clone(),
+            Session {
+                identity,
+                expires_at: now + self.ttl,
+            },
+        );
+        id
+    }
+
+    pub fn session(&self, id: &str) -> Option<Identity> {
+        let mut state = self.state.lock().unwrap();
+        state.prune(Instant::now());
+        Some(state.sessions.get(id)?.identity.clone())
+    }
+
+    pub fn remove_session(&self, id: &str) -> Option<Identity> {
+        let session = self.state.lock().unwrap().sessions.remove(id)?;
+        Some(session.identity)
+    }
+
+    /// Returns the `Set-Cookie` header value for a session. Passing `None`
+    /// deletes the cookie.
+    pub fn cookie(&self, id: Option<&str>) -> String {
+        self.set_cookie(COOKIE_NAME, id, self.ttl)
+    }
+
+    /// Returns the `Set-Cookie` header value binding a pending login to the
+    /// browser. Passing `None` deletes the cookie.
+    pub fn login_cookie(&self, state: Option<&str>) -> String {
+        self.set_cookie(LOGIN_COOKIE_NAME, state, LOGIN_TIMEOUT)
+    }
+
+    fn set_cookie(&self, name: &str, value: Option<&str>, max_age: Duration) -> String {
+        let secure = match self.provider {
+            Some(ref provider) if provider.is_https() => "; Secure",
+            _ => "",
+        };
+        match value {
+            Some(value) => format!(
+                "{name}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{secure}",
+                max_age.as_secs()
+            ),
+            None => format!("{name}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0{secure}"),
+        }
+    }
+
+    /// Extracts the session ID from the `Cookie` headers of a request.
+    pub fn session_id(headers: &http::HeaderMap) -> Option<String> {
+        cookie_value(headers, COOKIE_NAME)
+    }
+
+    /// Extracts the `state` of the pending login from the `Cookie` headers of
+    /// a request.
+    pub fn login_state(headers: &http::HeaderMap) -> Option<String> {
+        cookie_value(headers, LOGIN_COOKIE_NAME)
+    }
+}
+
+impl State {
+    /// Drops expired sessions, so that they do not pile up in memory.
+    fn prune(&mut self, now: Instant) {
+        self.sessions.retain(|_, session| session.expires_at > now);
+    }
+}
+
+fn cookie_value(headers: &http::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .find_map(|cookie| {
+            let (key, value) = cookie.trim().split_once('=')?;
+            (key == name).then(|| value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authentication(ttl: Duration) -> Authentication {
+        Authentication {
+            provider: None,
+            ttl,
+            state: Default::default(),
+        }
+    }
+
+    #[test]
+    fn login_requires_matching_cookie() {
+        let authentication = authentication(Duration::from_secs(60));
+        let (state, _) = authentication.begin_login("/".into());
+        assert!(authentication.finish_login(&state, None).is_none());
+        assert!(authentication.finish_login(&state, Some("other")).is_none());
+        let login = authentication.finish_login(&state, Some(&state)).unwrap();
+        assert_eq!(login.return_to, "/");
+        // A login can only be finished once
+        assert!(authentication.finish_login(&state, Some(&state)).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_pruned_on_lookup() {
+        let authentication = authentication(Duration::ZERO);
+        let identity = Identity {
+            user: super::super::User {
+                subject: "alice".into(),
+                name: "Alice".into(),
+                roles: Vec::new(),
+            },
+            id_token: None,
+        };
+        let id = authentication.create_session(identity);
+        assert!(authentication.session(&id).is_none());
+        assert!(authentication.state.lock().unwrap().sessions.is_empty());
+    }
+}
@@ -0,0 +1,492 @@
+//! Display lookup for codes that come without a display, e.g. ICD-10-GM or
+//! OPS codes from a KIS export.
+//!
+//! Displays are taken from offline code tables first. They are loaded at
+//! startup from the ClaML files listed in `TERMINOLOGY_CLAML`, e.g. the
+//! ICD-10-GM and OPS releases of the BfArM, so that lookups also work without
+//! network access. Other codes are resolved with `CodeSystem/$lookup` on the
+//! terminology server at `TERMINOLOGY_BASE_URL`, which is configured like the
+//! main FHIR client with the `TERMINOLOGY_` prefix. Results of the terminology
+//! server are appended as JSON lines to `TERMINOLOGY_CACHE` (defaults to
+//! `terminology-cache.jsonl`) and read back at startup.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{FhirClient, RequestError};
+
+/// Upper bound for concurrent requests to the terminology server, so that a
+/// bundle with many unknown codes does not flood it.
+const MAX_CONCURRENT_LOOKUPS: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Terminology {
+    /// Offline code tables: code by system, display by code
+    tables: Arc<HashMap<String, HashMap<String, String>>>,
+    server: Option<FhirClient>,
+    /// Displays from the terminology server by [`Code::key`]. `None` if the
+    /// server does not know the code.
+    cache: Arc<Mutex<HashMap<String, Option<String>>>>,
+    cache_file: Option<Arc<Mutex<File>>>,
+}
+
+/// A coding without a display.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Code {
+    system: String,
+    version: Option<String>,
+    code: String,
+}
+
+impl Code {
+    fn key(&self) -> String {
+        format!(
+            "{}|{}|{}",
+            self.system,
+            self.version.as_deref().unwrap_or_default(),
+            self.code
+        )
+    }
+}
+
+/// A line of the cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    display: Option<String>,
+}
+
+impl Terminology {
+    /// Creates the lookup from the environment:
+    ///
+    /// - `TERMINOLOGY_CLAML`: comma separated `system=path` pairs, e.g.
+    ///   `http://fhir.de/CodeSystem/bfarm/icd-10-gm=/data/icd10gm2025syst_claml.xml`
+    /// - `TERMINOLOGY_BASE_URL`: base URL of the terminology server. Without it
+    ///   only the offline tables are used.
+    /// - `TERMINOLOGY_CACHE`: cache file for the terminology server
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut tables = HashMap::new();
+        if let Ok(claml) = std::env::var("TERMINOLOGY_CLAML") {
+            for entry in claml.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (system, path) = entry
+                    .split_once('=')
+                    .context("TERMINOLOGY_CLAML must consist of system=path pairs")?;
+                let table = read_claml(Path::new(path))
+                    .with_context(|| format!("Failed to read ClaML file {path}"))?;
+                tracing::info!("Loaded {} codes of {system} from {path}", table.len());
+                tables
+                    .entry(system.to_string())
+                    .or_insert_with(HashMap::new)
+                    .extend(table);
+            }
+        }
+
+        let (server, cache, cache_file) = match std::env::var("TERMINOLOGY_BASE_URL") {
+            Ok(_) => {
+                let server = FhirClient::from_env_with_prefix("TERMINOLOGY")?;
+                let path =
+                    std::env::var("TERMINOLOGY_CACHE").unwrap_or("terminology-cache.jsonl".into());
+                let cache = read_cache(Path::new(&path))
+                    .with_context(|| format!("Failed to read terminology cache {path}"))?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open terminology cache {path}"))?;
+                (Some(server), cache, Some(Arc::new(Mutex::new(file))))
+            }
+            Err(_) => (None, HashMap::new(), None),
+        };
+
+        Ok(Self {
+            tables: Arc::new(tables),
+            server,
+            cache: Arc::new(Mutex::new(cache)),
+            cache_file,
+        })
+    }
+
+    /// Adds a display to every coding in `values` that has a system and a code
+    /// but no display, as far as the display can be found. Failures of the
+    /// terminology server are only logged, the codings then stay as they are.
+    pub async fn fill_displays(&self, values: &mut [Value]) {
+        if self.tables.is_empty() && self.server.is_none() {
+            return;
+        }
+        let mut codes = HashSet::new();
+        for value in values.iter() {
+            collect_codes(value, &mut codes);
+        }
+
+        let mut displays = HashMap::new();
+        let mut requests = tokio::task::JoinSet::new();
+        let permits = Arc::new(tokio::sync::Semaphore::new(MAX_CONCURRENT_LOOKUPS));
+        for code in codes {
+            if let Some(display) = self.lookup_offline(&code) {
+                displays.insert(code, display);
+                continue;
+            }
+            let Some(ref server) = self.server else {
+                continue;
+            };
+            match self.cache.lock().unwrap().get(&code.key()) {
+                Some(Some(display)) => {
+                    displays.insert(code, display.clone());
+                    continue;
+                }
+                Some(None) => continue,
+                None => {}
+            }
+            let server = server.clone();
+            let permits = permits.clone();
+            requests.spawn(async move {
+                // The semaphore is never closed
+                let _permit = permits.acquire_owned().await.ok();
+                let display = lookup(&server, &code).await;
+                (code, display)
+            });
+        }
+        let mut answers = Vec::new();
+        while let Some(result) = requests.join_next().await {
+            let Ok((code, display)) = result else {
+                continue;
+            };
+            match display {
+                Ok(display) => {
+                    answers.push(CacheEntry {
+                        key: code.key(),
+                        display: display.clone(),
+                    });
+                    if let Some(display) = display {
+                        displays.insert(code, display);
+                    }
+                }
+                Err(e) => tracing::warn!(
+                    "Failed to look up code {} in {}: {e}",
+                    code.code,
+                    code.system
+                ),
+            }
+        }
+        self.remember(answers).await;
+
+        if !displays.is_empty() {
+            for value in values.iter_mut() {
+                insert_displays(value, &displays);
+            }
+        }
+    }
+
+    fn lookup_offline(&self, code: &Code) -> Option<String> {
+        self.tables.get(&code.system)?.get(&code.code).cloned()
+    }
+
+    /// Caches the answers of the terminology server in memory and in the
+    /// cache file. The file is written on a blocking thread.
+    async fn remember(&self, answers: Vec<CacheEntry>) {
+        if answers.is_empty() {
+            return;
+        }
+        if let Some(ref file) = self.cache_file {
+            let file = file.clone();
+            let write = async {
+                let mut lines = String::new();
+                for entry in &answers {
+                    lines.push_str(&serde_json::to_string(entry)?);
+                    lines.push('\n');
+                }
+                tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+                    let mut file = file.lock().unwrap();
+                    file.write_all(lines.as_bytes())?;
+                    file.flush()
+                })
+                .await??;
+                anyhow::Ok(())
+            };
+            if let Err(e) = write.await {
+                tracing::warn!("Failed to write terminology cache: {e:#}");
+            }
+        }
+        let mut cache = self.cache.lock().unwrap();
+        for entry in answers {
+            cache.insert(entry.key, entry.display);
+        }
+    }
+}
+
+/// Looks up the display of a code with `CodeSystem/$lookup`. Returns `None` if
+/// the server does not know the code, i.e. responds with 400 or 404, which is
+/// cached like a display, and an error if the server could not be asked, e.g.
+/// because the request was not authorized or rate limited.
+/// http://hl7.org/fhir/R4/codesystem-operation-lookup.html
+async fn lookup(server: &FhirClient, code: &Code) -> Result<Option<String>, RequestError> {
+    let mut query = vec![
+        ("system", code.system.as_str()),
+        ("code", code.code.as_str()),
+    ];
+    if let Some(ref version) = code.version {
+        query.push(("version", version));
+    }
+    let query = serde_urlencoded::to_string(query).unwrap_or_default();
+    let url = server.url(&format!("CodeSystem/$lookup?{query}"));
+    let parameters = match server.get::<Value>(&url).await {
+        Ok(parameters) => parameters,
+        Err(RequestError::Status { status, .. })
+            if status == reqwest::StatusCode::BAD_REQUEST
+                || status == reqwest::StatusCode::NOT_FOUND =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    Ok(parameters["parameter"].as_array().and_then(|parameters| {
+        parameters
+            .iter()
+            .find(|parameter| parameter["name"] == "display")
+            .and_then(|parameter| parameter["valueString"].as_str())
+            .map(str::to_string)
+    }))
+}
+
+/// The elements of a `Coding`, including the `_name` elements of its primitives.
+const CODING_ELEMENTS: [&str; 11] = [
+    "id",
+    "extension",
+    "system",
+    "_system",
+    "version",
+    "_version",
+    "code",
+    "_code",
+    "display",
+    "_display",
+    "userSelected",
+];
+
+/// A coding is any object with a string `system` and `code` and no elements
+/// other than those of a `Coding`, which rules out e.g. a `Quantity` with a
+/// UCUM code.
+fn as_code(object: &serde_json::Map<String, Value>) -> Option<Code> {
+    if !object
+        .keys()
+        .all(|key| CODING_ELEMENTS.contains(&key.as_str()))
+    {
+        return None;
+    }
+    Some(Code {
+        system: object.get("system")?.as_str()?.to_string(),
+        version: object
+            .get("version")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        code: object.get("code")?.as_str()?.to_string(),
+    })
+}
+
+fn collect_codes(value: &Value, codes: &mut HashSet<Code>) {
+    match value {
+        Value::Object(object) => {
+            if !object.contains_key("display") {
+                codes.extend(as_code(object));
+            }
+            object
+                .values()
+                .for_each(|value| collect_codes(value, codes));
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_codes(value, codes)),
+        _ => {}
+    }
+}
+
+fn insert_displays(value: &mut Value, displays: &HashMap<Code, String>) {
+    match value {
+        Value::Object(object) => {
+            if !object.contains_key("display") {
+                if let Some(display) = as_code(object).and_then(|code| displays.get(&code)) {
+                    object.insert("display".into(), Value::String(display.clone()));
+                }
+            }
+            object
+                .values_mut()
+                .for_each(|value| insert_displays(value, displays));
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| insert_displays(value, displays)),
+        _ => {}
+    }
+}
+
+fn read_cache(path: &Path) -> anyhow::Result<HashMap<String, Option<String>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut cache = HashMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        // A crash while appending can leave a partial last line
+        match serde_json::from_str::<CacheEntry>(&line?) {
+            Ok(entry) => {
+                cache.insert(entry.key, entry.display);
+            }
+            Err(e) => tracing::warn!(
+                "Skipping line {} of terminology cache {}: {e}",
+                number + 1,
+                path.display()
+            ),
+        }
+    }
+    Ok(cache)
+}
+
+/// Reads the preferred labels of the classes in a ClaML file, e.g.
+/// `<Class code="A00.0"><Rubric kind="preferred"><Label>Cholera ...`.
+/// Labels can contain markup like `<Fragment>`, of which only the text is kept.
+fn read_claml(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    fn attribute(element: &BytesStart, name: &[u8]) -> anyhow::Result<Option<String>> {
+        Ok(match element.try_get_attribute(name)? {
+            Some(attribute) => Some(attribute.unescape_value()?.into_owned()),
+            None => None,
+        })
+    }
+
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+    let mut table = HashMap::new();
+    let mut code = None;
+    let mut preferred = false;
+    let mut label = None::<String>;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(element) => match element.name().as_ref() {
+                b"Class" => code = attribute(&element, b"code")?,
+                b"Rubric" => {
+                    preferred = attribute(&element, b"kind")?.as_deref() == Some("preferred")
+                }
+                b"Label" if code.is_some() && preferred => label = Some(String::new()),
+                _ => {}
+            },
+            Event::Text(text) => {
+                if let Some(ref mut label) = label {
+                    label.push_str(&text.unescape()?);
+                }
+            }
+            Event::End(element) => match element.name().as_ref() {
+                b"Label" => {
+                    if let (Some(code), Some(label)) = (&code, label.take()) {
+                        let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+                        table.entry(code.clone()).or_insert(label);
+                    }
+                }
+                b"Rubric" => preferred = false,
+                b"Class" => code = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_codings_are_codes() {
+        let coding = json!({"system": "http://loinc.org", "code": "718-7"});
+        assert!(as_code(coding.as_object().unwrap()).is_some());
+        let quantity = json!({"value": 5, "system": "http://unitsofmeasure.org", "code": "mg"});
+        assert!(as_code(quantity.as_object().unwrap()).is_none());
+    }
+
+    #[test]
+    fn skips_corrupt_cache_lines() {
+        let path =
+            std::env::temp_dir().join(format!("scout-terminology-{}.jsonl", std::process::id()));
+        std::fs::write(
+            &path,
+            "{\"key\":\"a|1|x\",\"display\":\"X\"}\n{\"key\":\"a|1|y\",\"disp\n",
+        )
+        .unwrap();
+        let cache = read_cache(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache["a|1|x"].as_deref(), Some("X"));
+    }
+
+    #[tokio::test]
+    async fn caches_unknown_codes_but_not_failures() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let display = json!({"resourceType": "Parameters", "parameter": [{"name": "display", "valueString": "Known"}]});
+        // Answers of the terminology server are only requested once, failures every time
+        for (code, response, requests) in [
+            (
+                "known",
+                ResponseTemplate::new(200).set_body_json(display),
+                1,
+            ),
+            ("unknown", ResponseTemplate::new(404), 1),
+            ("forbidden", ResponseTemplate::new(401), 2),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/CodeSystem/$lookup"))
+                .and(query_param("code", code))
+                .respond_with(response)
+                .expect(requests)
+                .mount(&server)
+                .await;
+        }
+        let path = std::env::temp_dir().join(format!(
+            "scout-terminology-cache-{}.jsonl",
+            std::process::id()
+        ));
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let terminology = Terminology {
+            tables: Default::default(),
+            server: Some(FhirClient::new(&server.uri())),
+            cache: Default::default(),
+            cache_file: Some(Arc::new(Mutex::new(file))),
+        };
+        let coding = |code: &str| json!({"system": "http://example.org", "code": code});
+
+        for _ in 0..2 {
+            let mut values = vec![json!({
+                "resourceType": "Observation",
+                "code": {"coding": [coding("known"), coding("unknown"), coding("forbidden")]}
+            })];
+            terminology.fill_displays(&mut values).await;
+            let codings = &values[0]["code"]["coding"];
+            assert_eq!(codings[0]["display"], "Known");
+            assert_eq!(codings[1].get("display"), None);
+            assert_eq!(codings[2].get("display"), None);
+        }
+
+        let cache = read_cache(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache["http://example.org||known"].as_deref(), Some("Known"));
+        assert_eq!(cache["http://example.org||unknown"], None);
+    }
+}
@@ -9,29 +9,7 @@ pub struct TableProps {
 
 #[component]
 pub fn Table(props: TableProps) -> Element {
-    let mut search_text = use_signal(|| "".to_string());
-    let filtered_rows = use_memo(move || {
-        let search_text = search_text.read().to_lowercase();
-        props
-            .rows
-            .iter()
-            .filter(|row| {
-                row.iter()
-                    .any(|cell| cell.to_lowercase().contains(&search_text))
-            })
-            .cloned()
-            .collect::<Vec<_>>()
-    });
     rsx! {
-        div {
-            class: "m-4",
-            input {
-                class: "border border-gray-300 rounded p-1",
-                placeholder: "Search table...",
-                value: "{search_text}",
-                oninput: move |event| search_text.set(event.value()),
-            }
-        }
         div {
             class: "grid gap-px p-px m-4",
             style: "grid-template-columns: repeat({props.headers.len()}, auto)",
@@ -44,7 +22,7 @@ pub fn Table(props: TableProps) -> Element {
                     }
                 }
             }
-            for row in filtered_rows().into_iter() {
+            for row in props.rows.iter().cloned() {
                 div {
                     class: "grid grid-cols-subgrid col-span-full",
                     for cell in row.iter() {
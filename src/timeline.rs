@@ -0,0 +1,382 @@
+//! The patient timeline. Encounters are shown as a tree following
+//! `Encounter.partOf`, with the conditions and procedures that reference an
+//! encounter nested inside it.
+
+use std::collections::{HashMap, HashSet};
+
+use dioxus::prelude::*;
+use itertools::Itertools;
+
+use crate::fhir::{self, TimelineEvent};
+use crate::{json, OptionalChip, Route};
+
+/// The timeline entries of a bundle, grouped by the encounter they belong to.
+struct Tree<'a> {
+    roots: Vec<&'a fhir::MixedEntry>,
+    /// Entries by the ID of their encounter
+    children: HashMap<&'a str, Vec<&'a fhir::MixedEntry>>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(bundle: &'a fhir::MixedBundle) -> Self {
+        let entries = bundle
+            .entry
+            .iter()
+            .filter(|entry| entry.resource.timeline_event().is_some())
+            .collect::<Vec<_>>();
+        // Only encounters that are in the timeline themselves can have children
+        let encounters = entries
+            .iter()
+            .filter_map(|entry| match entry.resource {
+                fhir::Resource::Encounter(ref encounter) => encounter.id.as_deref(),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let parent = |entry: &'a fhir::MixedEntry| {
+            entry
+                .resource
+                .encounter()?
+                .id_of("Encounter")
+                .filter(|id| encounters.contains(id))
+        };
+        let parents = entries
+            .iter()
+            .filter_map(|entry| match entry.resource {
+                fhir::Resource::Encounter(ref encounter) => {
+                    Some((encounter.id.as_deref()?, parent(entry)?))
+                }
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        // An encounter that is part of itself, directly or indirectly, is shown
+        // at the top level, so that the tree stays finite
+        let in_cycle = |entry: &'a fhir::MixedEntry| {
+            let fhir::Resource::Encounter(ref encounter) = entry.resource else {
+                return false;
+            };
+            let Some(start) = encounter.id.as_deref() else {
+                return false;
+            };
+            let mut visited = HashSet::new();
+            let mut current = start;
+            while let Some(&next) = parents.get(current) {
+                if next == start {
+                    return true;
+                }
+                if !visited.insert(next) {
+                    return false;
+                }
+                current = next;
+            }
+            false
+        };
+
+        let mut tree = Tree {
+            roots: Vec::new(),
+            children: HashMap::new(),
+        };
+        for entry in entries {
+            match parent(entry) {
+                Some(id) if !in_cycle(entry) => tree.children.entry(id).or_default().push(entry),
+                _ => tree.roots.push(entry),
+            }
+        }
+        tree
+    }
+}
+
+pub fn timeline(patient_id: &str, bundle: &fhir::MixedBundle) -> Element {
+    let tree = Tree::new(bundle);
+    entries(patient_id, bundle, &tree, &tree.roots)
+}
+
+fn entries(
+    patient_id: &str,
+    bundle: &fhir::MixedBundle,
+    tree: &Tree,
+    entries: &[&fhir::MixedEntry],
+) -> Element {
+    rsx! {
+        ol { class: "relative border-s border-gray-300",
+            for entry in entries.iter().sorted_by_key(|e| e.resource.timeline_event().unwrap().timestamp()) {
+                li { class: "mb-5 ms-4",
+                    // Target of the links between encounters and their diagnoses
+                    id: entry.resource.type_and_id().map(|(resource_type, id)| format!("{resource_type}/{id}")),
+                    div { class: "absolute w-3 h-3 bg-gray-300 rounded-full mt-1.5 -start-1.5 border border-white" }
+                    {card(patient_id, bundle, tree, entry)}
+                    if let Some((resource_type, resource_id)) = entry.resource.type_and_id().filter(|_| has_raw_json(patient_id, entry)) {
+                        Link {
+                            class: "text-sm text-gray-600 underline",
+                            to: Route::ResourceView { id: patient_id.to_string(), resource_type, resource_id },
+                            "Raw JSON"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the raw JSON of an entry can be shown, which only works for
+/// resources of the patient. `$everything` also returns the resources they
+/// refer to, e.g. practitioners and organizations, which are not modelled.
+fn has_raw_json(patient_id: &str, entry: &fhir::MixedEntry) -> bool {
+    match entry.resource {
+        fhir::Resource::Other(ref other) => {
+            json::references(&other.json, &format!("Patient/{patient_id}"))
+        }
+        _ => true,
+    }
+}
+
+fn card(
+    patient_id: &str,
+    bundle: &fhir::MixedBundle,
+    tree: &Tree,
+    entry: &fhir::MixedEntry,
+) -> Element {
+    match entry.resource {
+        fhir::Resource::Encounter(ref encounter) => {
+            let children = encounter
+                .id
+                .as_deref()
+                .and_then(|id| tree.children.get(id))
+                .cloned()
+                .unwrap_or_default();
+            let diagnoses = encounter
+                .diagnoses()
+                .into_iter()
+                .map(|diagnosis| {
+                    let code = match bundle.condition(&diagnosis.condition) {
+                        Some(condition) => condition.code(),
+                        None => diagnosis.condition.reference.clone().unwrap_or_default(),
+                    };
+                    let condition_id = diagnosis.condition.id_of("Condition").unwrap_or_default();
+                    (condition_id.to_string(), code, diagnosis.role())
+                })
+                .collect::<Vec<_>>();
+            rsx! {
+                details {
+                    open: !children.is_empty(),
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Encounter" }
+                            span { class: "text-gray-600", "{encounter.encounter_level()}" }
+                            OptionalChip { chip: encounter.status_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{encounter.formatted_timestamp()}"
+                    }
+                    p { "Class: {encounter.class()}" }
+                    p { "Visit number: {encounter.visit_number()}" }
+                    p { "Encounter level: {encounter.encounter_level()}" }
+                    p { "Service type: {encounter.service_type()}" }
+                    p { "Service provider: {encounter.service_provider()}" }
+                    if !diagnoses.is_empty() {
+                        p { "Diagnoses:" }
+                        ol { class: "list-decimal ms-6",
+                            for (condition_id, code, role) in diagnoses {
+                                li {
+                                    a { class: "underline", href: "#Condition/{condition_id}", "{code}" }
+                                    if !role.is_empty() {
+                                        " ({role})"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    if !children.is_empty() {
+                        div { class: "mt-3",
+                            {entries(patient_id, bundle, tree, &children)}
+                        }
+                    }
+                }
+            }
+        }
+        fhir::Resource::Condition(ref condition) => {
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Condition" }
+                            OptionalChip { chip: condition.clinical_status_chip() }
+                            OptionalChip { chip: condition.verification_status_chip() }
+                            OptionalChip { chip: condition.diagnosis_certainty_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{condition.formatted_timestamp()}"
+                    }
+                    p { "Code: {condition.code()}" }
+                    if let Some(primary) = condition.related_condition().and_then(|reference| bundle.condition(reference)) {
+                        p {
+                            "Primary code: "
+                            a { class: "underline", href: "#Condition/{primary.id()}", "{primary.code()}" }
+                        }
+                    }
+                    if let Some(id) = condition.id.as_deref() {
+                        for secondary in bundle.secondary_conditions(id) {
+                            p {
+                                "Secondary code: "
+                                a { class: "underline", href: "#Condition/{secondary.id()}", "{secondary.code()}" }
+                            }
+                        }
+                    }
+                    p { "Body site: {condition.body_site()}" }
+                    p { "Laterality: {condition.laterality()}" }
+                    p { "Onset: {condition.onset_start()}" }
+                    if let Some(id) = condition.id.as_deref() {
+                        for encounter in bundle.encounters_with_diagnosis(id) {
+                            p {
+                                "Diagnosis of "
+                                a { class: "underline", href: "#Encounter/{encounter.id()}",
+                                    "Encounter {encounter.visit_number()}"
+                                }
+                            }
+                        }
+                    }
+                    // p { "Notes: {condition.notes()}" }
+                }
+            }
+        }
+        fhir::Resource::Procedure(ref procedure) => {
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Procedure" }
+                            OptionalChip { chip: procedure.status_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{procedure.formatted_timestamp()}"
+                    }
+                    p { "Category: {procedure.category()}" }
+                    p { "Code: {procedure.code()}" }
+                    p { "Body Site: {procedure.body_site()}" }
+                    // p { "Notes: {procedure.note()}" }
+                }
+            }
+        }
+        fhir::Resource::Observation(ref observation) => {
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Observation" }
+                            OptionalChip { chip: observation.status_chip() }
+                            OptionalChip { chip: observation.interpretation_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{observation.formatted_timestamp()}"
+                    }
+                    p { "Category: {observation.category()}" }
+                    p { "Code: {observation.code()}" }
+                    p { "Value: {observation.value()}" }
+                    p { "Reference range: {observation.reference_range()}" }
+                }
+            }
+        }
+        fhir::Resource::MedicationStatement(ref statement) => {
+            let (medication, resolved) = statement.medication(bundle);
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Medication Statement" }
+                            OptionalChip { chip: statement.status_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{statement.formatted_timestamp()}"
+                    }
+                    p { "Medication: {medication}" }
+                    if let Some(resolved) = resolved {
+                        p { "Form: {resolved.form()}" }
+                        p { "Ingredients: {resolved.ingredients()}" }
+                    }
+                    p { "Reason: {statement.reason()}" }
+                    p { "Dosage: {statement.dosage()}" }
+                }
+            }
+        }
+        fhir::Resource::MedicationAdministration(ref administration) => {
+            let (medication, resolved) = administration.medication(bundle);
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Medication Administration" }
+                            OptionalChip { chip: administration.status_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{administration.formatted_timestamp()}"
+                    }
+                    p { "Medication: {medication}" }
+                    if let Some(resolved) = resolved {
+                        p { "Form: {resolved.form()}" }
+                        p { "Ingredients: {resolved.ingredients()}" }
+                    }
+                    p { "Dosage: {administration.dosage()}" }
+                }
+            }
+        }
+        fhir::Resource::MedicationRequest(ref request) => {
+            let (medication, resolved) = request.medication(bundle);
+            rsx! {
+                details {
+                    open: true,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "Medication Request" }
+                            OptionalChip { chip: request.status_chip() }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{request.formatted_timestamp()}"
+                    }
+                    p { "Medication: {medication}" }
+                    if let Some(resolved) = resolved {
+                        p { "Form: {resolved.form()}" }
+                        p { "Ingredients: {resolved.ingredients()}" }
+                    }
+                    p { "Intent: {request.intent()}" }
+                    p { "Dosage: {request.dosage()}" }
+                }
+            }
+        }
+        fhir::Resource::Other(ref other) => {
+            rsx! {
+                details {
+                    open: false,
+                    summary {
+                        div {
+                            class: "inline-flex items-center gap-1.5",
+                            h3 { class: "font-bold", "{other.resource_type()}" }
+                        }
+                    }
+                    time { class: "my-0.5 text-sm font-normal leading-none text-gray-600",
+                        "{other.formatted_timestamp()}"
+                    }
+                    crate::json::JsonTree { value: other.json.clone() }
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+}